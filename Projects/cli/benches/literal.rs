@@ -0,0 +1,102 @@
+use std::{
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use cli::{search, LiteralMatcher, Matcher, Searcher};
+
+// Compares the fast literal search against the line-by-line searches it replaces, on around 30MB of made-up log lines where only one line in ten thousand matches. That's the case the fast path is built for: big files and a query that's rarely there.
+
+const RUNS: usize = 5;
+
+fn main() {
+    let contents = generate();
+    println!(
+        "Searching {:.1} MB, best of {RUNS} runs",
+        contents.len() as f64 / 1_000_000.0
+    );
+
+    // A word that only turns up on the lines we want, and a query that shares most of its text with every single line, which is about as hard as it gets for skipping ahead.
+    for query in ["TimeoutError", "request_id=7f3a"] {
+        println!("\n{query:?}");
+        compare(&contents, query);
+    }
+}
+
+// The same literal search, minus the Finder, which stops the Searcher from taking its fast path. This is how run() searched before: one line at a time.
+struct LineByLine(LiteralMatcher);
+
+impl Matcher for LineByLine {
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        self.0.find_at(line, start)
+    }
+}
+
+fn compare(contents: &str, query: &str) {
+    let searcher = Searcher::default();
+    let line_by_line = LineByLine(LiteralMatcher::new(query));
+    let baselines = [
+        // The very first version, from the book.
+        time("lines().filter(contains)", || {
+            contents
+                .lines()
+                .filter(|line| line.contains(query.trim()))
+                .count()
+        }),
+        // What search() does now: a matcher asked about every line.
+        time("cli::search", || search(query, contents).len()),
+        time("Searcher, line by line", || {
+            searcher.count(&line_by_line, contents.as_bytes()).unwrap()
+        }),
+    ];
+
+    let matcher = LiteralMatcher::new(query);
+    let fast = [
+        time("Searcher::search", || {
+            searcher.search(&matcher, contents).len()
+        }),
+        time("Searcher::count", || {
+            searcher.count(&matcher, contents.as_bytes()).unwrap()
+        }),
+    ];
+
+    // Speedups are against the quickest of the old ways, so they're not flattering.
+    let baseline = baselines.iter().map(|(_, time)| *time).min().unwrap();
+    for (name, elapsed) in baselines.iter().chain(&fast) {
+        println!(
+            "  {name:<26} {:>8.2} ms   {:>5.1}x",
+            elapsed.as_secs_f64() * 1000.0,
+            baseline.as_secs_f64() / elapsed.as_secs_f64()
+        );
+    }
+}
+
+// Runs `search` a few times and keeps the fastest, which is the run least disturbed by whatever else the machine was doing. Every search has to find the same 40 lines, so a broken one can't look fast.
+fn time(name: &'static str, mut search: impl FnMut() -> usize) -> (&'static str, Duration) {
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let found = search();
+        best = best.min(start.elapsed());
+        assert_eq!(40, found, "{name} found the wrong number of lines");
+    }
+    (name, best)
+}
+
+fn generate() -> String {
+    let mut contents = String::new();
+    for number in 0..400_000 {
+        let (id, outcome) = if number % 10_000 == 0 {
+            (0x7f3a, "failed with TimeoutError after")
+        } else {
+            (number % 0x7000, "handled")
+        };
+        contents.push_str(&format!(
+            "2024-05-01T12:{:02}:{:02}Z INFO GET /api/items/{number} request_id={id:x} {outcome} {}ms\n",
+            number / 60 % 60,
+            number % 60,
+            number % 250
+        ));
+    }
+    contents
+}
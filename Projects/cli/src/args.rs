@@ -0,0 +1,341 @@
+use std::{error::Error, fmt};
+
+pub const USAGE: &str = "\
+Usage: cli [OPTIONS] QUERY [PATH]...
+       cli [OPTIONS] -e PATTERN... [PATH]...
+       cli [OPTIONS] -f FILE [PATH]...
+
+Searches each PATH for lines containing QUERY. Directories are searched
+recursively, skipping hidden files and anything .gitignore says to ignore,
+and with no PATH (or a PATH of -) standard input is read.
+
+Options:
+  -E, --regex                  Treat QUERY as a regular expression
+  -e, --regexp PATTERN         Search for PATTERN, can be given many times
+  -f, --file FILE              Search for every pattern in FILE, one per line
+  -i, --ignore-case            Match without caring about case (\"ß\" matches \"SS\",
+                               except with -E, where letters only match one to one)
+      --no-ignore-case         Match case exactly, even if IGNORE_CASE is set
+  -v, --invert-match           Select the lines that don't match
+  -w, --word-regexp            Only match whole words
+  -x, --line-regexp            Only match whole lines
+  -o, --only-matching          Print each match on its own line, not the whole line
+                               (with several patterns, followed by a tab and the
+                               pattern that matched)
+      --replace TEXT           Print lines with each match replaced by TEXT, where
+                               $1 or ${name} stand for capture groups with -E
+      --in-place[=SUFFIX]      Make the --replace changes in the files themselves,
+                               keeping the old ones with SUFFIX on the end if given
+      --dry-run                Print a diff of what --in-place would change, without
+                               changing anything
+  -a, --binary                 Search binary files as if they were text, instead of
+                               skipping them in directories and only saying whether
+                               they match
+      --encoding NAME          Read files without a BOM as NAME, e.g. utf-16le or
+                               latin1 (default: auto, which means UTF-8)
+      --lossy                  Search files that aren't valid in their encoding,
+                               with the bad parts replaced
+  -z, --search-zip             Search inside gzip, bzip2, xz and zstd files
+      --hidden                 Search hidden files and directories too
+      --no-ignore              Search files that .gitignore, .ignore or
+                               .git/info/exclude say to leave out
+  -g, --glob GLOB              Only search files matching GLOB, or leave them out
+                               if it starts with !, e.g. -g '*.rs' -g '!tests/'
+  -t, --type TYPE              Only search files of TYPE, e.g. rust, md or py
+  -T, --type-not TYPE          Don't search files of TYPE
+      --type-add TYPE:GLOB     Add GLOB (or several, split by commas) to TYPE
+  -n, --line-number            Show the line number of each line
+      --column                 Show the column of the first match
+  -b, --byte-offset            Show the byte offset of each line
+  -A, --after-context NUM      Show NUM lines after each match
+  -B, --before-context NUM     Show NUM lines before each match
+  -C, --context NUM            Show NUM lines before and after each match
+  -c, --count                  Only print how many lines were selected
+  -l, --files-with-matches     Only print the names of files with a match
+  -L, --files-without-match    Only print the names of files without a match
+      --json                   Print results as JSON Lines, for other programs
+                               (submatch offsets count from the start of the line
+                               as it appears in the JSON, which is UTF-8 text for
+                               files that were decoded)
+  -j, --threads NUM            Search NUM files at once (default: one per CPU)
+      --sort path|none         Print files in path order, or as they finish
+      --color WHEN             Highlight output: auto (default), always or never
+  -h, --help                   Print this help and exit
+  -V, --version                Print the version and exit
+
+Use -- to stop looking for options, e.g. `cli -- -v notes.txt` searches for \"-v\".
+
+Environment:
+  IGNORE_CASE       Set to anything other than \"\", \"0\" or \"false\" to ignore case
+  NO_COLOR          Set to anything but \"\" to turn color off unless --color=always
+  MINIGREP_COLORS   Colors to use, e.g. \"match=1;31:path=35:line=32:column=32\"
+  MINIGREP_TYPES    More file types, e.g. \"proto:*.proto;web:*.html,*.css\"";
+
+// Everything that can go wrong (or end early) while building a Config. Having an enum instead of a string means callers can match on what happened, and each variant keeps hold of the argument that caused it so the message can point right at it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    MissingQuery,
+    UnknownFlag(String),
+    // A flag like -A was given without the value it needs.
+    MissingValue(String),
+    // A value was given but wasn't something we could use, e.g. `-A lots`. `expected` says what would have been fine instead.
+    InvalidValue {
+        flag: String,
+        value: String,
+        expected: &'static str,
+    },
+    // A value was given to a flag that doesn't take one, e.g. `--count=3`.
+    UnexpectedValue {
+        flag: String,
+        value: String,
+    },
+    InvalidRegex {
+        pattern: String,
+        error: regex::Error,
+    },
+    // The file given to -f couldn't be read. We keep the io::Error's message rather than the error itself, since io::Error can't be cloned or compared.
+    PatternFile {
+        path: String,
+        error: String,
+    },
+    // A flag that doesn't mean anything without another one, e.g. --in-place without --replace.
+    NeedsFlag {
+        flag: String,
+        needs: &'static str,
+    },
+    // Two flags that can't be used together, e.g. --in-place and --count.
+    Conflict {
+        flag: &'static str,
+        other: &'static str,
+    },
+    // --help and --version aren't really errors, but they do mean we shouldn't go on to search anything, so main() prints them and exits successfully.
+    Help,
+    Version,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::MissingQuery => write!(f, "Didn't get a query string"),
+            ConfigError::UnknownFlag(flag) => write!(f, "Unknown option '{flag}'"),
+            ConfigError::MissingValue(flag) => write!(f, "{flag} needs a value"),
+            ConfigError::InvalidValue {
+                flag,
+                value,
+                expected,
+            } => write!(f, "{flag} expects {expected}, got '{value}'"),
+            ConfigError::UnexpectedValue { flag, value } => {
+                write!(f, "{flag} doesn't take a value, got '{value}'")
+            }
+            ConfigError::InvalidRegex { pattern, error } => {
+                write!(f, "Invalid regular expression '{pattern}': {error}")
+            }
+            ConfigError::PatternFile { path, error } => {
+                write!(f, "Couldn't read patterns from '{path}': {error}")
+            }
+            ConfigError::NeedsFlag { flag, needs } => write!(f, "{flag} only works with {needs}"),
+            ConfigError::Conflict { flag, other } => write!(f, "{flag} can't be used with {other}"),
+            ConfigError::Help => write!(f, "{USAGE}"),
+            ConfigError::Version => {
+                write!(
+                    f,
+                    "{} {}",
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION")
+                )
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+// A single thing on the command line, once we've pulled apart bundles like `-in` and `--context=2`. Flags keep their dashes, so Config::build can match on "-n" | "--line-number" directly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Arg {
+    Flag(String),
+    Positional(String),
+}
+
+// Splits raw arguments into flags, their values and positional arguments. It understands:
+// - short flags bundled together (`-in` is `-i -n`),
+// - values glued on to a short flag (`-A3`) or a long one (`--after-context=3`), or given as the next argument,
+// - `--` meaning everything after it is positional, even if it starts with a dash.
+pub struct ArgParser<I: Iterator<Item = String>> {
+    args: I,
+    // A bundle of short flags we're partway through, and how far into it we are.
+    shorts: Option<(String, usize)>,
+    // The `value` half of a `--flag=value` we just handed out.
+    long_value: Option<(String, String)>,
+    only_positional: bool,
+}
+
+impl<I: Iterator<Item = String>> ArgParser<I> {
+    pub fn new(args: I) -> ArgParser<I> {
+        ArgParser {
+            args,
+            shorts: None,
+            long_value: None,
+            only_positional: false,
+        }
+    }
+
+    pub fn next_arg(&mut self) -> Result<Option<Arg>, ConfigError> {
+        // If the last flag had an `=value` and nobody asked for it, that flag doesn't take a value.
+        if let Some((flag, value)) = self.long_value.take() {
+            return Err(ConfigError::UnexpectedValue { flag, value });
+        }
+
+        if let Some((bundle, position)) = &mut self.shorts {
+            if let Some(c) = bundle[*position..].chars().next() {
+                *position += c.len_utf8();
+                return Ok(Some(Arg::Flag(format!("-{c}"))));
+            }
+            self.shorts = None;
+        }
+
+        let arg = match self.args.next() {
+            Some(arg) => arg,
+            None => return Ok(None),
+        };
+
+        if self.only_positional {
+            return Ok(Some(Arg::Positional(arg)));
+        }
+        if arg == "--" {
+            self.only_positional = true;
+            return self.next_arg();
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            if let Some((name, value)) = long.split_once('=') {
+                let flag = format!("--{name}");
+                self.long_value = Some((flag.clone(), value.to_string()));
+                return Ok(Some(Arg::Flag(flag)));
+            }
+            return Ok(Some(Arg::Flag(arg)));
+        }
+        // A lone "-" isn't a flag, it's how you ask for standard input.
+        if arg.starts_with('-') && arg != "-" {
+            self.shorts = Some((arg, 1));
+            return self.next_arg();
+        }
+
+        Ok(Some(Arg::Positional(arg)))
+    }
+
+    // The value for the flag we just returned, wherever it was written.
+    pub fn value(&mut self, flag: &str) -> Result<String, ConfigError> {
+        if let Some((_, value)) = self.long_value.take() {
+            return Ok(value);
+        }
+        if let Some((bundle, position)) = self.shorts.take() {
+            if position < bundle.len() {
+                return Ok(bundle[position..].to_string());
+            }
+        }
+        self.args
+            .next()
+            .ok_or_else(|| ConfigError::MissingValue(flag.to_string()))
+    }
+
+    // For flags like --in-place where the value is optional. It has to be glued on with `=`, since otherwise there'd be no telling it apart from the next argument.
+    pub fn optional_value(&mut self) -> Option<String> {
+        self.long_value.take().map(|(_, value)| value)
+    }
+}
+
+// Flags like -A take a number, so we need to make sure that's what we got.
+pub fn parse_count(flag: &str, value: String) -> Result<usize, ConfigError> {
+    value.parse().map_err(|_| ConfigError::InvalidValue {
+        flag: flag.to_string(),
+        value,
+        expected: "a number",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Vec<Arg>, ConfigError> {
+        let mut parser = ArgParser::new(args.iter().map(|arg| arg.to_string()));
+        let mut parsed = Vec::new();
+        while let Some(arg) = parser.next_arg()? {
+            // Pretend -A and --context take values, the same as they do in Config::build.
+            if let Arg::Flag(flag) = &arg {
+                if flag == "-A" || flag == "--context" {
+                    parsed.push(Arg::Positional(parser.value(flag)?));
+                }
+            }
+            parsed.push(arg);
+        }
+        Ok(parsed)
+    }
+
+    fn flag(name: &str) -> Arg {
+        Arg::Flag(name.to_string())
+    }
+
+    fn positional(value: &str) -> Arg {
+        Arg::Positional(value.to_string())
+    }
+
+    #[test]
+    fn splits_bundled_short_flags() {
+        assert_eq!(
+            vec![flag("-i"), flag("-n"), positional("query"), positional("-")],
+            parse(&["-in", "query", "-"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn reads_values_in_every_spelling() {
+        assert_eq!(
+            vec![
+                positional("3"),
+                flag("-A"),
+                positional("4"),
+                flag("-A"),
+                positional("5"),
+                flag("--context"),
+                positional("6"),
+                flag("--context"),
+            ],
+            parse(&["-A3", "-A", "4", "--context=5", "--context", "6"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn optional_values_must_be_glued() {
+        let args = ["--in-place=.bak", "--in-place", "query"];
+        let mut parser = ArgParser::new(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(Some(flag("--in-place")), parser.next_arg().unwrap());
+        assert_eq!(Some(String::from(".bak")), parser.optional_value());
+        assert_eq!(Some(flag("--in-place")), parser.next_arg().unwrap());
+        assert_eq!(None, parser.optional_value());
+        assert_eq!(Some(positional("query")), parser.next_arg().unwrap());
+    }
+
+    #[test]
+    fn double_dash_ends_flags() {
+        assert_eq!(
+            vec![flag("-n"), positional("-v"), positional("--count")],
+            parse(&["-n", "--", "-v", "--count"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn errors_name_the_flag() {
+        assert_eq!(
+            Err(ConfigError::MissingValue(String::from("-A"))),
+            parse(&["-A"])
+        );
+        assert_eq!(
+            Err(ConfigError::UnexpectedValue {
+                flag: String::from("--count"),
+                value: String::from("3"),
+            }),
+            parse(&["--count=3"])
+        );
+    }
+}
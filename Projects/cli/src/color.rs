@@ -0,0 +1,87 @@
+// When to color the output, as picked with --color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    // Only when stdout is a terminal. Colors piped into a file or another program just turn into escape-code noise.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+// The colors for each part of the output, stored as the numbers that go inside an ANSI escape code (so "1;31" is bold red). An empty string means leave that part alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Colors {
+    pub matched: String,
+    pub path: String,
+    pub line_number: String,
+    pub column: String,
+}
+
+impl Default for Colors {
+    // The same choices grep makes: bold red matches, magenta file names, and green numbers.
+    fn default() -> Colors {
+        Colors {
+            matched: String::from("1;31"),
+            path: String::from("35"),
+            line_number: String::from("32"),
+            column: String::from("32"),
+        }
+    }
+}
+
+impl Colors {
+    // Reads a spec like "match=1;31:path=35", starting from the defaults and changing only what's mentioned. Like grep does with GREP_COLORS, anything we don't understand is skipped rather than treated as an error, since a typo in an environment variable shouldn't stop a search from running.
+    pub fn parse(spec: &str) -> Colors {
+        let mut colors = Colors::default();
+
+        for entry in spec.split(':') {
+            let (name, value) = match entry.split_once('=') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            // Only digits and semicolons belong in an escape code. Letting anything else through could mess up the terminal.
+            if !value.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                continue;
+            }
+
+            let slot = match name {
+                "match" => &mut colors.matched,
+                "path" => &mut colors.path,
+                "line" => &mut colors.line_number,
+                "column" => &mut colors.column,
+                _ => continue,
+            };
+            *slot = value.to_string();
+        }
+
+        colors
+    }
+}
+
+// Wraps text in the escape codes that turn a color on and back off again.
+pub fn paint(color: &str, text: &str) -> String {
+    if color.is_empty() {
+        return text.to_string();
+    }
+    format!("\x1b[{color}m{text}\x1b[0m")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_overrides_only_what_is_given() {
+        let colors = Colors::parse("match=4;33:path=:bogus=1:line=red");
+
+        assert_eq!("4;33", colors.matched);
+        assert_eq!("", colors.path);
+        assert_eq!("32", colors.line_number);
+    }
+
+    #[test]
+    fn paint_wraps_in_escape_codes() {
+        assert_eq!("\x1b[1;31mfish\x1b[0m", paint("1;31", "fish"));
+        assert_eq!("fish", paint("", "fish"));
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    fmt,
+    io::{self, Read},
+};
+
+// The compression formats -z can see inside. We recognise them by the magic bytes they start with rather than by file name, so a rotated log called `app.log.1` that's really gzip still gets searched, and a `.gz` that was already decompressed isn't mangled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+// The longest magic number we look for, so the most we ever need to peek at.
+pub const MAGIC_LEN: usize = 6;
+
+impl Format {
+    pub fn detect(start: &[u8]) -> Option<Format> {
+        if start.starts_with(&[0x1f, 0x8b]) {
+            Some(Format::Gzip)
+        } else if start.starts_with(b"BZh") {
+            Some(Format::Bzip2)
+        } else if start.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+            Some(Format::Xz)
+        } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Format::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Format::Gzip => "gzip",
+            Format::Bzip2 => "bzip2",
+            Format::Xz => "xz",
+            Format::Zstd => "zstd",
+        };
+        write!(f, "{name}")
+    }
+}
+
+// A reader that hands out the decompressed contents of `reader`. Each format's decoder is its own type, so they go in a Box to give the rest of the search one type to deal with.
+pub struct Decompressor<'a> {
+    format: Format,
+    decoder: Box<dyn Read + 'a>,
+}
+
+impl<'a> Decompressor<'a> {
+    pub fn new(format: Format, reader: impl Read + 'a) -> io::Result<Decompressor<'a>> {
+        // The multi-stream decoders keep going after the first stream ends, since `cat a.gz b.gz > c.gz` (which log rotation does a lot) is a perfectly good gzip file that plain GzDecoder would stop halfway through.
+        let decoder: Box<dyn Read + 'a> = match format {
+            Format::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+            Format::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+            Format::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(reader)),
+            // zstd reads through a BufReader of its own, and keeps going over concatenated frames without being asked.
+            Format::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        };
+        Ok(Decompressor { format, decoder })
+    }
+}
+
+impl Read for Decompressor<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // The decoders' own errors ("corrupt deflate stream" and so on) don't say which step went wrong, so we add that.
+        self.decoder.read(buf).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("couldn't decompress {}: {err}", self.format),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn decompress_all(bytes: &[u8]) -> io::Result<String> {
+        let format = Format::detect(bytes).expect("compressed input");
+        let mut text = String::new();
+        Decompressor::new(format, bytes)?.read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    #[test]
+    fn detects_formats_by_magic_bytes() {
+        assert_eq!(Some(Format::Gzip), Format::detect(b"\x1f\x8b\x08\x00"));
+        assert_eq!(Some(Format::Bzip2), Format::detect(b"BZh91AY&SY"));
+        assert_eq!(Some(Format::Xz), Format::detect(b"\xfd7zXZ\x00\x00"));
+        assert_eq!(Some(Format::Zstd), Format::detect(b"\x28\xb5\x2f\xfd\x04"));
+        assert_eq!(None, Format::detect(b"plain text"));
+        assert_eq!(None, Format::detect(b"\x1f"));
+    }
+
+    #[test]
+    fn reads_every_format_and_every_stream() {
+        let mut gzip = Vec::new();
+        for text in ["one fish\n", "two fish\n"] {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+            encoder.write_all(text.as_bytes()).unwrap();
+            gzip.extend(encoder.finish().unwrap());
+        }
+        assert_eq!("one fish\ntwo fish\n", decompress_all(&gzip).unwrap());
+
+        let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::fast());
+        bzip2.write_all(b"red fish\n").unwrap();
+        assert_eq!(
+            "red fish\n",
+            decompress_all(&bzip2.finish().unwrap()).unwrap()
+        );
+
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 1);
+        xz.write_all(b"blue fish\n").unwrap();
+        assert_eq!(
+            "blue fish\n",
+            decompress_all(&xz.finish().unwrap()).unwrap()
+        );
+
+        let zstd = zstd::encode_all(&b"old fish\n"[..], 1).unwrap();
+        assert_eq!("old fish\n", decompress_all(&zstd).unwrap());
+    }
+
+    #[test]
+    fn corrupt_input_says_what_failed() {
+        let err = decompress_all(b"\x1f\x8b\x08\x00garbage").unwrap_err();
+        assert!(err.to_string().starts_with("couldn't decompress gzip: "));
+    }
+}
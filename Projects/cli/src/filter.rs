@@ -0,0 +1,189 @@
+use std::{collections::BTreeMap, path::Path};
+
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+
+use crate::gitignore::IgnoreFile;
+
+// The file types --type and --type-not know about out of the box, each with the globs that pick out its files. They only ever match a file's name, never the directories it's in.
+const DEFAULT_TYPES: &[(&str, &[&str])] = &[
+    ("c", &["*.c", "*.h"]),
+    ("cpp", &["*.cpp", "*.cc", "*.cxx", "*.hpp", "*.hh", "*.hxx"]),
+    ("css", &["*.css", "*.scss"]),
+    ("go", &["*.go"]),
+    ("html", &["*.html", "*.htm"]),
+    ("java", &["*.java"]),
+    ("js", &["*.js", "*.jsx", "*.mjs", "*.cjs"]),
+    ("json", &["*.json"]),
+    ("md", &["*.md", "*.markdown"]),
+    ("py", &["*.py"]),
+    ("rust", &["*.rs", "Cargo.toml"]),
+    ("sh", &["*.sh", "*.bash", "*.zsh"]),
+    ("toml", &["*.toml"]),
+    ("ts", &["*.ts", "*.tsx"]),
+    ("txt", &["*.txt"]),
+    ("yaml", &["*.yaml", "*.yml"]),
+];
+
+// Every file type we know, by name. A BTreeMap keeps them sorted, which makes error messages and the like come out the same every time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileTypes {
+    types: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for FileTypes {
+    fn default() -> FileTypes {
+        FileTypes {
+            types: DEFAULT_TYPES
+                .iter()
+                .map(|(name, globs)| {
+                    let globs = globs.iter().map(|glob| glob.to_string()).collect();
+                    (name.to_string(), globs)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl FileTypes {
+    // Adds globs to a type from a definition like "proto:*.proto" or "web:*.html,*.css", creating the type if it's new. Returns false if the definition doesn't make sense.
+    pub fn add(&mut self, definition: &str) -> bool {
+        let Some((name, globs)) = definition.split_once(':') else {
+            return false;
+        };
+        let globs: Vec<&str> = globs.split(',').filter(|glob| !glob.is_empty()).collect();
+        if name.is_empty() || globs.is_empty() || globs.iter().any(|glob| Glob::new(glob).is_err())
+        {
+            return false;
+        }
+        self.types
+            .entry(name.to_string())
+            .or_default()
+            .extend(globs.into_iter().map(String::from));
+        true
+    }
+
+    pub fn globs(&self, name: &str) -> Option<&[String]> {
+        self.types.get(name).map(Vec::as_slice)
+    }
+}
+
+// The -g globs. They work like the lines of a .gitignore turned inside out: a glob picks files to search, and one starting with ! leaves files (or whole directories) out. When several match, the last one given wins.
+pub struct Overrides {
+    rules: IgnoreFile,
+    // Once there's a glob saying what to search, anything it doesn't match gets left out.
+    has_includes: bool,
+}
+
+impl Overrides {
+    pub fn new(root: &Path, globs: &[String]) -> Overrides {
+        Overrides {
+            rules: IgnoreFile::parse(root, &globs.join("\n")),
+            has_includes: globs.iter().any(|glob| !glob.starts_with('!')),
+        }
+    }
+
+    // Some(true) to search `path` no matter what, Some(false) to leave it out no matter what, and None to let the rest of the rules decide.
+    pub fn check(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        // IgnoreFile's "ignored" is our "included", since our globs say what to keep.
+        match self.rules.check(path, is_dir) {
+            Some(included) => Some(included),
+            // Directories still need looking inside, since `-g '*.rs'` is about the files in them.
+            None if self.has_includes && !is_dir => Some(false),
+            None => None,
+        }
+    }
+}
+
+// The --type and --type-not filters, turned into globs over file names.
+pub struct TypeFilter {
+    // None when no --type was given, which means every type is fine.
+    select: Option<GlobSet>,
+    negate: GlobSet,
+}
+
+impl TypeFilter {
+    pub fn new(select: &[String], negate: &[String]) -> TypeFilter {
+        TypeFilter {
+            select: (!select.is_empty()).then(|| glob_set(select)),
+            negate: glob_set(negate),
+        }
+    }
+
+    pub fn allows(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name() else {
+            return true;
+        };
+        if self.negate.is_match(name) {
+            return false;
+        }
+        self.select
+            .as_ref()
+            .is_none_or(|select| select.is_match(name))
+    }
+}
+
+// Every glob has been checked before it gets here (FileTypes::add checks the ones for types), so one that won't build shouldn't happen, but skipping it beats panicking.
+fn glob_set(globs: &[String]) -> GlobSet {
+    let mut set = GlobSetBuilder::new();
+    for glob in globs {
+        if let Ok(glob) = GlobBuilder::new(glob).literal_separator(true).build() {
+            set.add(glob);
+        }
+    }
+    set.build().unwrap_or_else(|_| GlobSet::empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_custom_types() {
+        let mut types = FileTypes::default();
+        assert!(types.add("proto:*.proto"));
+        assert!(types.add("rust:*.rs.in,build.rs"));
+        assert!(!types.add("nothing"));
+        assert!(!types.add("bad:[z-a"));
+
+        assert_eq!(Some(&["*.proto".to_string()][..]), types.globs("proto"));
+        assert_eq!(
+            Some(&["*.rs", "Cargo.toml", "*.rs.in", "build.rs"].map(String::from)[..]),
+            types.globs("rust")
+        );
+        assert_eq!(None, types.globs("klingon"));
+    }
+
+    #[test]
+    fn last_matching_glob_wins() {
+        let globs = ["*.rs", "!tests/", "!*_gen.rs", "keep_gen.rs"].map(String::from);
+        let overrides = Overrides::new(Path::new("repo"), &globs);
+        let check = |path: &str, is_dir| overrides.check(Path::new(path), is_dir);
+
+        assert_eq!(Some(true), check("repo/src/main.rs", false));
+        assert_eq!(Some(false), check("repo/README.md", false));
+        assert_eq!(None, check("repo/src", true));
+        assert_eq!(Some(false), check("repo/tests", true));
+        assert_eq!(Some(false), check("repo/src/parser_gen.rs", false));
+        assert_eq!(Some(true), check("repo/src/keep_gen.rs", false));
+
+        // With only ! globs, everything else is left to the other rules.
+        let overrides = Overrides::new(Path::new("repo"), &["!*.md".to_string()]);
+        assert_eq!(None, overrides.check(Path::new("repo/main.rs"), false));
+    }
+
+    #[test]
+    fn types_match_file_names() {
+        let types = FileTypes::default();
+        let rust = types.globs("rust").unwrap();
+        let md = types.globs("md").unwrap();
+
+        let filter = TypeFilter::new(rust, &[]);
+        assert!(filter.allows(Path::new("src/main.rs")));
+        assert!(filter.allows(Path::new("Cargo.toml")));
+        assert!(!filter.allows(Path::new("pyproject.toml")));
+
+        let filter = TypeFilter::new(&[], md);
+        assert!(!filter.allows(Path::new("docs/README.md")));
+        assert!(filter.allows(Path::new("src/main.rs")));
+    }
+}
@@ -0,0 +1,115 @@
+use std::ops::Range;
+
+// Case folding turns text into a form where "the same letters, ignoring case" become byte-for-byte equal, which is what a case-insensitive search really wants. Plain to_lowercase() gets most of the way, but not all of it: "ß" and "SS" should match, yet lowercasing leaves them as "ß" and "ss".
+//
+// Folding can change the length of the text ("ß" turns into "ss"), so alongside the folded text we remember where in the original each folded byte came from. That way a match found in the folded text can be reported in terms of the line the user actually has.
+pub struct Folded {
+    pub text: String,
+    // origins[i] is the byte offset in the original of the character that produced folded byte i. None means the folding didn't move anything, which is always the case for plain ASCII.
+    origins: Option<Vec<usize>>,
+}
+
+impl Folded {
+    // Turns a range in the folded text back into a range in the original. If a match starts or ends partway through a character that folded into several (say, just the first "s" of the "ss" that came from "ß"), the range grows to cover that whole character.
+    pub fn original_range(&self, original: &str, range: Range<usize>) -> Range<usize> {
+        let origins = match &self.origins {
+            Some(origins) => origins,
+            None => return range,
+        };
+
+        let start = origins.get(range.start).copied().unwrap_or(original.len());
+        let end = if range.end == range.start {
+            start
+        } else {
+            // The last folded byte in the match came from some original character. The match ends where that character does.
+            let last = origins[range.end - 1];
+            last + original[last..].chars().next().map_or(0, char::len_utf8)
+        };
+        start..end
+    }
+
+    // original_range() for every match from a search of the folded text. Several matches inside what one character folded into (each "s" of the "ss" from "ß", say) all grow into that same character, so we only keep the first of them. Anything else would hand out overlapping ranges, which nothing after us expects.
+    pub fn original_ranges(
+        &self,
+        original: &str,
+        ranges: impl Iterator<Item = Range<usize>>,
+    ) -> Vec<Range<usize>> {
+        let mut mapped: Vec<Range<usize>> = Vec::new();
+        for range in ranges {
+            let range = self.original_range(original, range);
+            if mapped.last().is_some_and(|last| range.start < last.end) {
+                continue;
+            }
+            mapped.push(range);
+        }
+        mapped
+    }
+}
+
+pub fn fold(text: &str) -> Folded {
+    // ASCII only has the 26 letter pairs to worry about, and folding it never changes any lengths, so we can skip the bookkeeping entirely.
+    if text.is_ascii() {
+        return Folded {
+            text: text.to_ascii_lowercase(),
+            origins: None,
+        };
+    }
+
+    let mut folded = String::with_capacity(text.len());
+    let mut origins = Vec::with_capacity(text.len());
+    for (offset, c) in text.char_indices() {
+        let before = folded.len();
+        fold_char(c, &mut folded);
+        origins.resize(origins.len() + folded.len() - before, offset);
+    }
+
+    Folded {
+        text: folded,
+        origins: Some(origins),
+    }
+}
+
+fn fold_char(c: char, folded: &mut String) {
+    match c {
+        // The capital dotted I from Turkish and Azeri. Lowercasing it gives "i" followed by a combining dot, which would stop "İstanbul" from ever matching "istanbul", so we fold it to a plain "i" instead.
+        'İ' => folded.push('i'),
+        // Uppercasing first and then lowercasing is what takes care of the tricky ones: "ß" becomes "SS" and then "ss", the final sigma "ς" becomes "Σ" and then "σ", the ligature "ﬁ" becomes "FI" and then "fi", and the dotless "ı" becomes "I" and then "i". Both sides of the search go through this same function, so they always agree.
+        _ => c
+            .to_uppercase()
+            .flat_map(char::to_lowercase)
+            .for_each(|c| folded.push(c)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_special_cases() {
+        assert_eq!("strasse", fold("Straße").text);
+        assert_eq!("strasse", fold("STRASSE").text);
+        assert_eq!("istanbul", fold("İstanbul").text);
+        assert_eq!("istanbul", fold("ıstanbul").text);
+        // The second one ends in a final sigma, "ς", which has to fold to the same thing as "Σ" and "σ".
+        assert_eq!("σοφοσ", fold("ΣΟΦΟΣ").text);
+        assert_eq!("σοφοσ", fold("σοφος").text);
+    }
+
+    #[test]
+    fn maps_ranges_back_to_the_original() {
+        let original = "Die Straße";
+        let folded = fold(original);
+        assert_eq!("die strasse", folded.text);
+
+        assert_eq!(4..11, folded.original_range(original, 4..11));
+        // Matching only the first half of the "ss" still highlights the whole "ß".
+        assert_eq!(8..10, folded.original_range(original, 8..9));
+
+        // "İ" is two bytes but folds to a one byte "i", so everything after it moves along by one.
+        let original = "İstanbul";
+        let folded = fold(original);
+        assert_eq!(2..4, folded.original_range(original, 1..3));
+        assert_eq!(0..9, folded.original_range(original, 0..8));
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+// The rules from one .gitignore (or .ignore, or .git/info/exclude), which say whether paths under the directory it sits in should be left out of a search. We follow the same rules git does:
+// - blank lines and lines starting with # are skipped,
+// - a leading ! turns a rule around, so it brings back something an earlier rule left out,
+// - a trailing / means the rule only applies to directories,
+// - a rule with a / anywhere else is anchored to the ignore file's directory, and one without can match at any depth,
+// - *, ? and [...] don't match across a /, but ** does.
+//
+// Turning each rule into a glob and letting globset match the lot in one go is much quicker than trying every rule one at a time, which matters when a big repo has hundreds of them.
+#[derive(Debug)]
+pub struct IgnoreFile {
+    // Paths get matched relative to this directory, with `prefix` in front. The prefix is only there for ignore files in a directory above where the walk started.
+    dir: PathBuf,
+    prefix: PathBuf,
+    globs: GlobSet,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rule {
+    negated: bool,
+    dir_only: bool,
+}
+
+impl IgnoreFile {
+    pub fn parse(dir: &Path, contents: &str) -> IgnoreFile {
+        let mut globs = GlobSetBuilder::new();
+        let mut rules = Vec::new();
+
+        for line in contents.lines() {
+            let Some((glob, rule)) = parse_rule(line) else {
+                continue;
+            };
+            let glob = GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .backslash_escape(true)
+                .build();
+            // git quietly skips a pattern it can't make sense of, so we do too.
+            if let Ok(glob) = glob {
+                globs.add(glob);
+                rules.push(rule);
+            }
+        }
+
+        IgnoreFile {
+            dir: dir.to_path_buf(),
+            prefix: PathBuf::new(),
+            // Every glob already built on its own, so building the set can't fail, but an empty set does the right thing if it somehow did.
+            globs: globs.build().unwrap_or_else(|_| GlobSet::empty()),
+            rules,
+        }
+    }
+
+    // Reads an ignore file from disk. A file that isn't there just means there are no rules, which is the usual case.
+    pub fn read(dir: &Path, file: &Path) -> io::Result<Option<IgnoreFile>> {
+        match fs::read_to_string(file) {
+            Ok(contents) => Ok(Some(IgnoreFile::parse(dir, &contents))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    // For an ignore file in a directory above the walk's root: paths are given relative to `root`, and `prefix` is how to get from the ignore file's directory down to it.
+    pub fn above(mut self, root: &Path, prefix: PathBuf) -> IgnoreFile {
+        self.dir = root.to_path_buf();
+        self.prefix = prefix;
+        self
+    }
+
+    // Some(true) if the last rule that matches `path` ignores it, Some(false) if that rule is a ! one, and None if nothing here says anything about it.
+    pub fn check(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.dir).ok()?;
+        let relative = self.prefix.join(relative);
+        self.globs
+            .matches(&relative)
+            .into_iter()
+            .rev()
+            .map(|index| self.rules[index])
+            .find(|rule| is_dir || !rule.dir_only)
+            .map(|rule| !rule.negated)
+    }
+}
+
+// Turns one line of an ignore file into a glob, or None for blank lines and comments.
+fn parse_rule(line: &str) -> Option<(String, Rule)> {
+    if line.starts_with('#') {
+        return None;
+    }
+    // Trailing spaces don't count, unless there's a backslash keeping the last one.
+    let mut line = line;
+    while line.ends_with(' ') && !line.ends_with("\\ ") {
+        line = &line[..line.len() - 1];
+    }
+
+    let negated = line.starts_with('!');
+    if negated {
+        line = &line[1..];
+    }
+    let dir_only = line.ends_with('/');
+    if dir_only {
+        line = &line[..line.len() - 1];
+    }
+    let anchored = line.contains('/');
+    let line = line.strip_prefix('/').unwrap_or(line);
+    if line.is_empty() {
+        return None;
+    }
+
+    let glob = if anchored {
+        line.to_string()
+    } else {
+        format!("**/{line}")
+    };
+    Some((glob, Rule { negated, dir_only }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_gitignore_rules() {
+        let rules = IgnoreFile::parse(
+            Path::new("repo"),
+            "# build output\n/target/\n*.log\n!keep.log\ndocs/*.html\n\nspaced\\  \n",
+        );
+        let check = |path: &str, is_dir| rules.check(Path::new(path), is_dir);
+
+        assert_eq!(Some(true), check("repo/target", true));
+        // Anchored, so only the one at the top, and only a directory.
+        assert_eq!(None, check("repo/crates/target", true));
+        assert_eq!(None, check("repo/target", false));
+        // Not anchored, so at any depth.
+        assert_eq!(Some(true), check("repo/a/b/debug.log", false));
+        assert_eq!(Some(false), check("repo/a/keep.log", false));
+        // * doesn't match across a /.
+        assert_eq!(Some(true), check("repo/docs/index.html", false));
+        assert_eq!(None, check("repo/docs/api/index.html", false));
+        assert_eq!(Some(true), check("repo/spaced ", false));
+        // Outside the directory the file is in.
+        assert_eq!(None, check("elsewhere/debug.log", false));
+    }
+
+    #[test]
+    fn files_above_the_root_match_with_a_prefix() {
+        let rules = IgnoreFile::parse(Path::new("/repo"), "/crates/*/target/\n")
+            .above(Path::new("."), PathBuf::from("crates"));
+
+        assert_eq!(Some(true), rules.check(Path::new("./cli/target"), true));
+        assert_eq!(None, rules.check(Path::new("./cli/src"), true));
+    }
+}
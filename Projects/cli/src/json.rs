@@ -0,0 +1,193 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::RawMatch;
+
+// --json prints one JSON object per line (JSON Lines), so a program can read results as they arrive without waiting for the whole search to finish. For each file there's a "begin", a "match" for every selected line, and an "end" with that file's numbers, then a single "summary" once everything is done.
+//
+// Anything that might not be UTF-8 (a line, a piece of a line, or a path) is written as {"text": "..."} when it is, and as {"bytes": "..."} holding base64 when it isn't, so odd files never make the output invalid.
+//
+// A match's "byte_offset" is where its line starts in the file, the same number -b gives. The "start" and "end" of each submatch are offsets into "line" as it's written in that record instead, so a program can slice the text it was given. For a file we decoded from something like UTF-16, that's the UTF-8 text, not the file's own bytes.
+
+// The numbers reported at the end of each file, and added up for the summary.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub matched_lines: usize,
+    pub matches: usize,
+    pub bytes_searched: usize,
+}
+
+// Totals across every file, collected from all the worker threads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub files_searched: usize,
+    pub files_with_matches: usize,
+    pub stats: Stats,
+}
+
+impl Summary {
+    pub fn add_file(&mut self, stats: &Stats) {
+        self.files_searched += 1;
+        if stats.matched_lines > 0 {
+            self.files_with_matches += 1;
+        }
+        self.stats.matched_lines += stats.matched_lines;
+        self.stats.matches += stats.matches;
+        self.stats.bytes_searched += stats.bytes_searched;
+    }
+}
+
+pub fn write_begin(out: &mut impl Write, path: &Path) -> io::Result<()> {
+    writeln!(out, "{{\"type\":\"begin\",\"path\":{}}}", path_data(path))
+}
+
+pub fn write_match(out: &mut impl Write, path: &Path, found: &RawMatch) -> io::Result<()> {
+    let submatches: Vec<String> = found
+        .ranges
+        .iter()
+        .map(|range| {
+            format!(
+                "{{\"start\":{},\"end\":{},\"match\":{}}}",
+                range.start,
+                range.end,
+                data(&found.bytes[range.clone()])
+            )
+        })
+        .collect();
+
+    writeln!(
+        out,
+        "{{\"type\":\"match\",\"path\":{},\"line_number\":{},\"byte_offset\":{},\"line\":{},\"submatches\":[{}]}}",
+        path_data(path),
+        found.line_number,
+        found.byte_offset,
+        data(found.bytes),
+        submatches.join(",")
+    )
+}
+
+pub fn write_end(out: &mut impl Write, path: &Path, stats: &Stats) -> io::Result<()> {
+    writeln!(
+        out,
+        "{{\"type\":\"end\",\"path\":{},\"stats\":{}}}",
+        path_data(path),
+        stats_object(stats)
+    )
+}
+
+pub fn write_summary(out: &mut impl Write, summary: &Summary) -> io::Result<()> {
+    writeln!(
+        out,
+        "{{\"type\":\"summary\",\"files_searched\":{},\"files_with_matches\":{},\"stats\":{}}}",
+        summary.files_searched,
+        summary.files_with_matches,
+        stats_object(&summary.stats)
+    )
+}
+
+fn stats_object(stats: &Stats) -> String {
+    format!(
+        "{{\"matched_lines\":{},\"matches\":{},\"bytes_searched\":{}}}",
+        stats.matched_lines, stats.matches, stats.bytes_searched
+    )
+}
+
+fn path_data(path: &Path) -> String {
+    data(path.as_os_str().as_encoded_bytes())
+}
+
+fn data(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => format!("{{\"text\":{}}}", string(text)),
+        Err(_) => format!("{{\"bytes\":\"{}\"}}", base64(bytes)),
+    }
+}
+
+// A JSON string literal. Quotes, backslashes and control characters are the only things JSON insists on escaping, everything else (including any Unicode) can go in as it is.
+fn string(text: &str) -> String {
+    let mut output = String::with_capacity(text.len() + 2);
+    output.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if c < ' ' => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+// Standard base64 with padding. Every 3 bytes become 4 characters, each one carrying 6 bits.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut output = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |group, (index, &byte)| {
+                group | (byte as u32) << (16 - 8 * index)
+            });
+        // A chunk of n bytes needs n + 1 characters, and the rest of the 4 are padding.
+        for index in 0..4 {
+            if index <= chunk.len() {
+                output.push(ALPHABET[(group >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Range;
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!("", base64(b""));
+        assert_eq!("Zg==", base64(b"f"));
+        assert_eq!("Zm8=", base64(b"fo"));
+        assert_eq!("Zm9v", base64(b"foo"));
+        assert_eq!("//4A", base64(b"\xff\xfe\x00"));
+    }
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(
+            r#""say \"hi\"\\\n\u0007 ß""#,
+            string("say \"hi\"\\\n\x07 ß")
+        );
+    }
+
+    #[test]
+    fn writes_a_match_event() {
+        let found = RawMatch {
+            line_number: 2,
+            byte_offset: 8,
+            ranges: vec![Range { start: 2, end: 6 }],
+            bytes: b"\xff fish",
+        };
+        let mut out = Vec::new();
+        write_match(&mut out, Path::new("notes.txt"), &found).unwrap();
+
+        assert_eq!(
+            concat!(
+                r#"{"type":"match","path":{"text":"notes.txt"},"line_number":2,"byte_offset":8,"#,
+                r#""line":{"bytes":"/yBmaXNo"},"submatches":[{"start":2,"end":6,"match":{"text":"fish"}}]}"#,
+                "\n"
+            ),
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -1,16 +1,132 @@
-use std::{env, error::Error, fs};
+use std::{
+    cell::RefCell,
+    env,
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Write},
+    iter,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use encoding_rs::{Encoding, UTF_8};
+use globset::Glob;
+
+mod args;
+mod color;
+mod decompress;
+mod filter;
+mod fold;
+mod gitignore;
+mod json;
+mod lines;
+mod literal;
+mod matcher;
+mod parallel;
+mod printer;
+mod replace;
+mod searcher;
+mod transcode;
+mod walk;
+
+use crate::args::{parse_count, Arg, ArgParser};
+pub use crate::args::{ConfigError, USAGE};
+pub use crate::color::{ColorChoice, Colors};
+use crate::decompress::{Decompressor, Format};
+use crate::filter::FileTypes;
+use crate::json::Summary;
+pub use crate::lines::LineReader;
+pub use crate::literal::Finder;
+pub use crate::matcher::{
+    Boundary, BoundedMatcher, CaseInsensitiveMatcher, LiteralMatcher, Matcher, MultiMatcher,
+    RegexMatcher,
+};
+pub use crate::parallel::Sort;
+use crate::printer::Printer;
+pub use crate::replace::InPlace;
+use crate::replace::Rewrite;
+use crate::searcher::looks_binary;
+pub use crate::searcher::{RawMatch, SearchLine, Searcher};
+use crate::transcode::{Offsets, Transcoder};
+pub use crate::walk::{Walk, WalkError, WalkOptions};
 
 pub struct Config {
-    pub query: String,
-    pub file_path: String,
+    // What to search for. Usually just the query, but -e and -f can give as many as you like, and a line matches if any of them do.
+    pub patterns: Vec<String>,
+    pub file_paths: Vec<String>,
     pub ignore_case: bool,
+    pub regex: bool,
+    // Whether matches have to be whole words (-w) or whole lines (-x).
+    pub boundary: Boundary,
+    pub line_number: bool,
+    pub column: bool,
+    pub byte_offset: bool,
+    pub before_context: usize,
+    pub after_context: usize,
+    pub invert_match: bool,
+    // Print only the matched parts of each line (-o).
+    pub only_matching: bool,
+    // What to put in place of each match (--replace). With -E, $1 or ${name} stand for what a capture group matched.
+    pub replace: Option<String>,
+    // Make the replacements in the files themselves instead of printing them (--in-place or --dry-run).
+    pub in_place: Option<InPlace>,
+    // Search binary files as if they were text (-a).
+    pub binary_as_text: bool,
+    // What to decode files from when they don't start with a BOM. None means UTF-8, which needs no decoding.
+    pub encoding: Option<&'static Encoding>,
+    // Replace anything that can't be decoded with U+FFFD instead of giving up on the file (--lossy).
+    pub lossy: bool,
+    // Search inside compressed files (-z).
+    pub search_zip: bool,
+    // What to leave out when walking a directory.
+    pub walk: WalkOptions,
+    pub output_mode: OutputMode,
+    // How many files to search at the same time.
+    pub threads: usize,
+    pub sort: Sort,
+    pub color: ColorChoice,
+    pub colors: Colors,
+}
+
+// What run() prints for each file. Only Lines needs to see every match, the others just need a summary, which lets them skip a lot of work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    #[default]
+    Lines,
+    // How many lines were selected (-c).
+    Count,
+    // Just the names of files with at least one selected line (-l).
+    FilesWithMatches,
+    // Just the names of files without any (-L).
+    FilesWithoutMatch,
+    // One JSON object per event, for other programs to read (--json).
+    Json,
+}
+
+// A single matching line, along with everything we know about where it was found. Holding on to the line as a &str slice (rather than a copied String) means the Match borrows from the contents we searched, which is what the 'a lifetime is telling us.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match<'a> {
+    // Counting from 1, the way editors do.
+    pub line_number: usize,
+    // Where the line starts, counted in bytes from the beginning of the contents.
+    pub byte_offset: usize,
+    // The parts of the line the query matched, as byte ranges into `line`.
+    pub ranges: Vec<Range<usize>>,
+    pub line: &'a str,
 }
 
 impl Config {
     // pub fn build(args: &[String]) -> Result<Config, &'static str> { // old implementation changed to use our iterators.
 
     // Also, we're able to use mut keyword into the args param to make it mutable since we're taking ownership of args
-    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, &'static str> {
+    // The error type started out as &'static str, then became a String so a bad regex could explain itself, and is now a ConfigError enum so callers can tell the different problems apart.
+    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, ConfigError> {
         //     now that we implemented the Iterator trait by using args, we can call the next() method on it.
 
         // if args.len() < 3 {
@@ -24,42 +140,758 @@ impl Config {
 
         args.next(); // We do this since the first value that gets returned by env::args is the name of the program, which we don't need.
 
-        let query = match args.next() {
-            // from here on, it's a pretty simple match pattern using next() to get to the value we put into the query field.
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
+        // Flags can show up anywhere, so we pull them out first and keep the rest in order as our positional arguments. ArgParser takes care of the fiddly spellings (-in, -A3, --context=2, --) so all we have to do here is match on the flag names.
+        let mut regex = false;
+        let mut ignore_case = None;
+        let mut whole_word = false;
+        let mut whole_line = false;
+        let mut line_number = false;
+        let mut column = false;
+        let mut byte_offset = false;
+        let mut invert_match = false;
+        let mut only_matching = false;
+        let mut replace = None;
+        let mut in_place = None;
+        let mut dry_run = false;
+        let mut binary_as_text = false;
+        let mut encoding = None;
+        let mut lossy = false;
+        let mut search_zip = false;
+        let mut walk = WalkOptions::default();
+        // Type names and definitions, with the flag each came from. We can only look the names up once every --type-add has been seen.
+        let mut type_flags = Vec::new();
+        let mut output_mode = OutputMode::Lines;
+        let mut threads = None;
+        let mut sort = Sort::Unsorted;
+        // Only Some when --color was given, which --in-place needs to know.
+        let mut color = None;
+        // -A and -B win over -C no matter which order they were given in, so we hold on to all three until the end.
+        let mut before_context = None;
+        let mut after_context = None;
+        let mut context = None;
+        let mut positional = Vec::new();
+        // Patterns from -e and -f. If there are any, the query isn't taken from the positional arguments.
+        let mut patterns = Vec::new();
+        let mut patterns_given = false;
+
+        let mut parser = ArgParser::new(args);
+        while let Some(arg) = parser.next_arg()? {
+            let flag = match arg {
+                Arg::Positional(value) => {
+                    positional.push(value);
+                    continue;
+                }
+                Arg::Flag(flag) => flag,
+            };
+
+            match flag.as_str() {
+                "-A" | "--after-context" => {
+                    after_context = Some(parse_count(&flag, parser.value(&flag)?)?)
+                }
+                "-B" | "--before-context" => {
+                    before_context = Some(parse_count(&flag, parser.value(&flag)?)?)
+                }
+                "-C" | "--context" => context = Some(parse_count(&flag, parser.value(&flag)?)?),
+                "-E" | "--regex" => regex = true,
+                "-e" | "--regexp" => {
+                    patterns.push(parser.value(&flag)?);
+                    patterns_given = true;
+                }
+                "-f" | "--file" => {
+                    let path = parser.value(&flag)?;
+                    let contents =
+                        fs::read_to_string(&path).map_err(|err| ConfigError::PatternFile {
+                            path: path.clone(),
+                            error: err.to_string(),
+                        })?;
+                    // One pattern per line. An empty file gives no patterns at all, which matches nothing, the same as grep.
+                    patterns.extend(contents.lines().map(String::from));
+                    patterns_given = true;
+                }
+                "-i" | "--ignore-case" => ignore_case = Some(true),
+                "--no-ignore-case" => ignore_case = Some(false),
+                "-v" | "--invert-match" => invert_match = true,
+                "-w" | "--word-regexp" => whole_word = true,
+                "-x" | "--line-regexp" => whole_line = true,
+                "-o" | "--only-matching" => only_matching = true,
+                // No -r, since that's grep's --recursive, and anyone typing `cli -r foo src` out of habit would end up replacing instead of searching.
+                "--replace" => replace = Some(parser.value(&flag)?),
+                // `--in-place=` with nothing after it is the same as no backup at all.
+                "--in-place" => {
+                    in_place = Some(parser.optional_value().filter(|suffix| !suffix.is_empty()))
+                }
+                "--dry-run" => dry_run = true,
+                // grep calls this --text and ripgrep calls it --binary, so we take either.
+                "-a" | "--binary" | "--text" => binary_as_text = true,
+                "--encoding" => {
+                    let value = parser.value(&flag)?;
+                    // Any name a browser would understand works, so "latin1", "utf-16" and "cp1252" are all fine.
+                    encoding = match Encoding::for_label(value.as_bytes()) {
+                        _ if value == "auto" => None,
+                        Some(found) => Some(found),
+                        None => {
+                            return Err(ConfigError::InvalidValue {
+                                flag,
+                                value,
+                                expected: "an encoding like 'utf-16le' or 'latin1'",
+                            })
+                        }
+                    }
+                }
+                "--lossy" => lossy = true,
+                "-z" | "--search-zip" => search_zip = true,
+                "--hidden" => walk.hidden = true,
+                "--no-ignore" => walk.no_ignore = true,
+                "-g" | "--glob" => {
+                    let glob = parser.value(&flag)?;
+                    // Checking the glob here means a typo gets reported straight away, rather than quietly matching nothing.
+                    if Glob::new(glob.strip_prefix('!').unwrap_or(&glob)).is_err() {
+                        return Err(ConfigError::InvalidValue {
+                            flag,
+                            value: glob,
+                            expected: "a glob like '*.rs' or '!target/'",
+                        });
+                    }
+                    walk.globs.push(glob);
+                }
+                "-t" | "--type" | "-T" | "--type-not" | "--type-add" => {
+                    let value = parser.value(&flag)?;
+                    type_flags.push((flag, value));
+                }
+                "-c" | "--count" => output_mode = OutputMode::Count,
+                "-l" | "--files-with-matches" => output_mode = OutputMode::FilesWithMatches,
+                "-L" | "--files-without-match" => output_mode = OutputMode::FilesWithoutMatch,
+                "--json" => output_mode = OutputMode::Json,
+                "-n" | "--line-number" => line_number = true,
+                "--column" => column = true,
+                "-b" | "--byte-offset" => byte_offset = true,
+                "-j" | "--threads" => match parse_count(&flag, parser.value(&flag)?)? {
+                    0 => {
+                        return Err(ConfigError::InvalidValue {
+                            flag,
+                            value: String::from("0"),
+                            expected: "at least one thread",
+                        })
+                    }
+                    count => threads = Some(count),
+                },
+                "--sort" => {
+                    sort = match parser.value(&flag)?.as_str() {
+                        "path" => Sort::Path,
+                        "none" => Sort::Unsorted,
+                        other => {
+                            return Err(ConfigError::InvalidValue {
+                                value: other.to_string(),
+                                flag,
+                                expected: "'path' or 'none'",
+                            })
+                        }
+                    }
+                }
+                "--color" | "--colour" => {
+                    color = Some(match parser.value(&flag)?.as_str() {
+                        "auto" => ColorChoice::Auto,
+                        "always" => ColorChoice::Always,
+                        "never" => ColorChoice::Never,
+                        other => {
+                            return Err(ConfigError::InvalidValue {
+                                value: other.to_string(),
+                                flag,
+                                expected: "'auto', 'always' or 'never'",
+                            })
+                        }
+                    })
+                }
+                "-h" | "--help" => return Err(ConfigError::Help),
+                "-V" | "--version" => return Err(ConfigError::Version),
+                _ => return Err(ConfigError::UnknownFlag(flag)),
+            }
+        }
+        let mut args = positional.into_iter();
+
+        if !patterns_given {
+            let query = match args.next() {
+                // from here on, it's a pretty simple match pattern using next() to get to the value we put into the query field.
+                Some(arg) => arg,
+                None => return Err(ConfigError::MissingQuery),
+            };
+            patterns.push(query);
+        }
+
+        // Everything after the query is a path to search, so we simply collect whatever is left. With no paths at all we read standard input, just as if "-" had been given, so the cli can sit at the end of a pipeline.
+        let mut file_paths: Vec<String> = args.collect();
+        if file_paths.is_empty() {
+            file_paths.push(String::from("-"));
+        }
+
+        // let ignore_case = env::var("IGNORE_CASE").is_ok();
+        // Just checking is_ok() meant even IGNORE_CASE=0 turned it on. Now the variable has to actually say yes, and a flag on the command line beats it either way.
+        let ignore_case = ignore_case.unwrap_or_else(|| match env::var("IGNORE_CASE") {
+            Ok(value) => !matches!(value.as_str(), "" | "0" | "false"),
+            Err(_) => false,
+        });
+
+        // NO_COLOR (https://no-color.org) asks for no color unless the user explicitly says otherwise, so it only changes what auto means and --color=always still wins.
+        let color_given = color.is_some();
+        let mut color = color.unwrap_or(ColorChoice::Auto);
+        if color == ColorChoice::Auto
+            && env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+        {
+            color = ColorChoice::Never;
+        }
+        let colors = match env::var("MINIGREP_COLORS") {
+            Ok(spec) => Colors::parse(&spec),
+            Err(_) => Colors::default(),
         };
 
-        let file_path = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file path"),
+        // Custom file types can be kept in MINIGREP_TYPES, e.g. "proto:*.proto;web:*.html,*.css", as well as given with --type-add. Like MINIGREP_COLORS, anything in there that doesn't make sense is skipped.
+        let mut types = FileTypes::default();
+        if let Ok(spec) = env::var("MINIGREP_TYPES") {
+            for definition in spec.split(';') {
+                types.add(definition.trim());
+            }
+        }
+        for (flag, value) in &type_flags {
+            if flag == "--type-add" && !types.add(value) {
+                return Err(ConfigError::InvalidValue {
+                    flag: flag.clone(),
+                    value: value.clone(),
+                    expected: "a type like 'name:*.ext' or 'name:*.a,*.b'",
+                });
+            }
+        }
+        for (flag, value) in type_flags {
+            let selected = match flag.as_str() {
+                "-t" | "--type" => &mut walk.types,
+                "-T" | "--type-not" => &mut walk.types_not,
+                _ => continue,
+            };
+            match types.globs(&value) {
+                Some(globs) => selected.extend_from_slice(globs),
+                None => {
+                    return Err(ConfigError::InvalidValue {
+                        flag,
+                        value,
+                        expected: "a file type like 'rust' or 'md'",
+                    })
+                }
+            }
+        }
+
+        // Editing files only makes sense once we know what to replace matches with. --dry-run on its own is fine, it means --in-place without the writing.
+        let in_place = match (in_place, dry_run) {
+            (None, false) => None,
+            (backup, dry_run) => Some(InPlace {
+                backup: backup.flatten(),
+                dry_run,
+            }),
         };
+        if let Some(in_place) = &in_place {
+            let flag = if in_place.dry_run {
+                "--dry-run"
+            } else {
+                "--in-place"
+            };
+            if replace.is_none() {
+                return Err(ConfigError::NeedsFlag {
+                    flag: flag.to_string(),
+                    needs: "--replace",
+                });
+            }
+            // Editing works on every match in every line and doesn't print any results, only a diff with --dry-run, so none of these would do anything.
+            let other = [
+                (invert_match, "--invert-match"),
+                (output_mode == OutputMode::Count, "--count"),
+                (
+                    output_mode == OutputMode::FilesWithMatches,
+                    "--files-with-matches",
+                ),
+                (
+                    output_mode == OutputMode::FilesWithoutMatch,
+                    "--files-without-match",
+                ),
+                (output_mode == OutputMode::Json, "--json"),
+                (only_matching, "--only-matching"),
+                (after_context.is_some(), "--after-context"),
+                (before_context.is_some(), "--before-context"),
+                (context.is_some(), "--context"),
+                (line_number, "--line-number"),
+                (column, "--column"),
+                (byte_offset, "--byte-offset"),
+                (color_given, "--color"),
+            ]
+            .into_iter()
+            .find_map(|(given, other)| given.then_some(other));
+            if let Some(other) = other {
+                return Err(ConfigError::Conflict { flag, other });
+            }
+        }
 
-        let ignore_case = env::var("IGNORE_CASE").is_ok();
-        Ok(Config {
-            query,
-            file_path,
+        // A whole line is always whole words too, so -x wins if both are given.
+        let boundary = if whole_line {
+            Boundary::Line
+        } else if whole_word {
+            Boundary::Word
+        } else {
+            Boundary::Anywhere
+        };
+
+        let config = Config {
+            patterns,
+            file_paths,
             ignore_case,
-        })
+            regex,
+            boundary,
+            line_number,
+            column,
+            byte_offset,
+            before_context: before_context.or(context).unwrap_or(0),
+            after_context: after_context.or(context).unwrap_or(0),
+            invert_match,
+            only_matching,
+            replace,
+            in_place,
+            binary_as_text,
+            encoding,
+            lossy,
+            search_zip,
+            walk,
+            output_mode,
+            // By default we use one thread per core. If the OS can't tell us how many that is, one thread still works.
+            threads: threads
+                .unwrap_or_else(|| thread::available_parallelism().map_or(1, |count| count.get())),
+            sort,
+            color,
+            colors,
+        };
+
+        // Compile the pattern once here so a broken regex gets reported as an argument problem instead of blowing up halfway through run().
+        config.matcher()?;
+        Ok(config)
+    }
+
+    pub fn matcher(&self) -> Result<Box<dyn Matcher>, ConfigError> {
+        if self.regex {
+            let matcher = RegexMatcher::any_of(&self.patterns, self.ignore_case, self.boundary)
+                .map_err(|(pattern, error)| ConfigError::InvalidRegex { pattern, error })?;
+            return Ok(Box::new(matcher));
+        }
+
+        let matcher: Box<dyn Matcher> = match self.patterns.as_slice() {
+            [query] if self.ignore_case => Box::new(CaseInsensitiveMatcher::new(query)),
+            [query] => Box::new(LiteralMatcher::new(query)),
+            // MultiMatcher checks -w and -x itself, see the comment on its `boundary`.
+            patterns => {
+                return Ok(Box::new(MultiMatcher::with_boundary(
+                    patterns,
+                    self.ignore_case,
+                    self.boundary,
+                )))
+            }
+        };
+        if self.boundary == Boundary::Anywhere {
+            return Ok(matcher);
+        }
+        Ok(Box::new(BoundedMatcher::new(matcher, self.boundary)))
     }
 }
 
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.file_path)?;
+    let paths: Vec<&Path> = config.file_paths.iter().map(Path::new).collect();
+
+    let file_searcher = FileSearcher {
+        matcher: config.matcher()?,
+        searcher: Searcher::new(&config),
+        printer: Printer::new(&config),
+        output_mode: config.output_mode,
+        edit: config.replace.clone().zip(config.in_place.clone()),
+        encoding: config.encoding,
+        lossy: config.lossy,
+        search_zip: config.search_zip,
+        track_offsets: config.byte_offset || config.output_mode == OutputMode::Json,
+        // Once more than one file is involved, a bare line isn't much use unless we also say which file it came from.
+        with_path: paths.len() > 1 || paths.iter().any(|path| path.is_dir()),
+        summary: Mutex::new(Summary::default()),
+        stop: AtomicBool::new(false),
+    };
 
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
+    let jobs = jobs(&paths, &config.walk);
+    // Without a directory to walk we know exactly how many files there are, and there's no use for more threads than that.
+    let threads = if paths.iter().any(|path| path.is_dir()) {
+        config.threads
     } else {
-        search(&config.query, &contents)
+        config.threads.min(paths.len())
     };
 
-    for line in results {
-        println!("{line}");
+    let failed = if threads > 1 {
+        // The workers take turns with stdout, so none of them can be holding the lock on it for the whole search.
+        parallel::search_in_parallel(
+            jobs,
+            threads,
+            config.sort,
+            &mut io::stdout(),
+            &file_searcher.stop,
+            |job, output| file_searcher.run_job(job, output),
+        )?
+    } else {
+        // With only one thing to search there's nothing to gain from threads, and writing straight to stdout means results from a pipe show up as soon as they're found rather than when the pipe closes.
+        let mut out = io::stdout().lock();
+        let mut failed = false;
+        for job in jobs {
+            failed |= file_searcher.run_job(&job, &mut out)?;
+        }
+        failed
+    };
+    let mut out = io::stdout().lock();
+    if config.output_mode == OutputMode::Json {
+        let summary = *file_searcher.summary.lock().unwrap();
+        json::write_summary(&mut out, &summary)?;
+    }
+    out.flush()?;
+
+    // We kept going past any bad paths so the good ones still got searched, but the caller should still hear that something went wrong.
+    if failed {
+        return Err("some files could not be searched".into());
     }
     Ok(())
 }
 
-pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+// How much of a walked file we look at to decide whether it's binary. Big enough to get past most file headers, small enough that it's nothing next to reading the file.
+const BINARY_PEEK: usize = 8 * 1024;
+
+// Everything there is to search, in the order a single thread would search it. Directories get walked as the jobs are taken, so the first file found is searched straight away rather than once the whole tree has been listed.
+fn jobs<'a>(paths: &'a [&Path], options: &'a WalkOptions) -> impl Iterator<Item = Job> + 'a {
+    paths
+        .iter()
+        .flat_map(|&path| -> Box<dyn Iterator<Item = Job>> {
+            if !path.is_dir() {
+                return Box::new(iter::once(Job::File(path.to_path_buf())));
+            }
+            // When we're given a directory we behave like grep -r: every file underneath gets searched.
+            Box::new(
+                Walk::with_options(path, options.clone()).map(|entry| match entry {
+                    Ok(file) => Job::WalkedFile(file),
+                    Err(err) => Job::WalkError(err),
+                }),
+            )
+        })
+}
+
+// One unit of work for run(). Directory walk errors get a spot in the sequence too, so they're reported in the same order a single thread would have hit them.
+enum Job {
+    // A path given on the command line.
+    File(PathBuf),
+    // A file we found while walking a directory.
+    WalkedFile(PathBuf),
+    WalkError(WalkError),
+}
+
+// Everything we need to search one file, bundled together so run() doesn't have to pass half a dozen arguments around for every file. It only ever gets read from, so one FileSearcher is shared by every worker thread.
+struct FileSearcher {
+    matcher: Box<dyn Matcher>,
+    searcher: Searcher,
+    printer: Printer,
+    output_mode: OutputMode,
+    // The replacement and what to do with it, when we're editing files rather than searching them.
+    edit: Option<(String, InPlace)>,
+    encoding: Option<&'static Encoding>,
+    lossy: bool,
+    search_zip: bool,
+    // Whether we're going to show byte offsets, which for a file we decode means working out where each line was in the original.
+    track_offsets: bool,
+    with_path: bool,
+    // Running totals for the --json summary. Every worker adds to it once per file, so it sits behind a Mutex.
+    summary: Mutex<Summary>,
+    // Set once writing our output has failed on another thread, which makes every search still going stop at its next read.
+    stop: AtomicBool,
+}
+
+impl FileSearcher {
+    // Searches one job, writing results to `out` and problems to stderr. A file we can't read is reported and skipped, since one bad file shouldn't throw away the results from all the others. Returns whether anything went wrong, or an error if writing to `out` failed, since then nothing else is going to be seen either.
+    fn run_job(&self, job: &Job, out: &mut impl Write) -> io::Result<bool> {
+        let (path, result) = match job {
+            Job::File(path) => (path, self.search_file(path, out, false)),
+            // Files we found ourselves while walking a directory get skipped if they look binary, since a directory is bound to be full of images and build output nobody meant to search. If you name a file directly though, you'll want to hear about it (as "Binary file ... matches"). With -a there's no such thing as a binary file, so nothing gets skipped.
+            Job::WalkedFile(path) => (
+                path,
+                self.search_file(path, out, !self.searcher.binary_as_text),
+            ),
+            Job::WalkError(err) => {
+                eprintln!("{err}");
+                return Ok(true);
+            }
+        };
+
+        match result {
+            Ok(()) => Ok(false),
+            Err(err) if is_output_error(&err) => Err(err),
+            Err(err) => {
+                eprintln!("{}: {err}", path.display());
+                Ok(true)
+            }
+        }
+    }
+
+    fn search_file(&self, path: &Path, out: &mut impl Write, skip_binary: bool) -> io::Result<()> {
+        if let Some((replacement, in_place)) = &self.edit {
+            return self.edit_file(path, out, skip_binary, replacement, in_place);
+        }
+        if path == Path::new("-") {
+            return self.search_reader(
+                UntilStopped::new(io::stdin().lock(), &self.stop),
+                Path::new("(standard input)"),
+                out,
+                skip_binary,
+            );
+        }
+        // No BufReader needed here, the Searcher reads through its own fixed-size buffer.
+        self.search_reader(
+            UntilStopped::new(File::open(path)?, &self.stop),
+            path,
+            out,
+            skip_binary,
+        )
+    }
+
+    // Makes the --replace changes in the file itself, or with --dry-run prints a diff of them. Unlike searching, this reads the whole file first, since it's all going to be written out again anyway.
+    fn edit_file(
+        &self,
+        path: &Path,
+        out: &mut impl Write,
+        skip_binary: bool,
+        replacement: &str,
+        in_place: &InPlace,
+    ) -> io::Result<()> {
+        if path == Path::new("-") {
+            return Err(io::Error::other("standard input can't be edited in place"));
+        }
+        let bytes = fs::read(path)?;
+        // Writing back a file we had to decompress or decode would mean compressing or encoding it again, and any difference in how that came out would be a change nobody asked for. So we only edit UTF-8 text, BOM or not, and leave everything else alone (quietly, for files we came across in a directory).
+        let other_encoding =
+            transcode::sniff(&bytes, self.encoding).is_some_and(|(encoding, _)| encoding != UTF_8);
+        let contents = match String::from_utf8(bytes) {
+            Ok(contents) if !other_encoding && !contents.contains('\0') => contents,
+            _ if skip_binary => return Ok(()),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "only UTF-8 text can be edited in place",
+                ))
+            }
+        };
+
+        let rewrite = Rewrite::new(self.matcher.as_ref(), &contents, replacement);
+        if !rewrite.changed() {
+            return Ok(());
+        }
+        if in_place.dry_run {
+            return out
+                .write_all(rewrite.diff(path).as_bytes())
+                .map_err(output_error);
+        }
+        replace::write_in_place(path, &rewrite.contents(), in_place.backup.as_deref())
+    }
+
+    // Before searching anything we take a peek at how it starts, to see whether it's compressed (with -z), binary, or in some encoding other than UTF-8. The bytes we peeked at still need searching, so they go back in front of the rest each time.
+    //
+    // This first step takes care of compression, so everything after it sees the decompressed contents.
+    fn search_reader(
+        &self,
+        mut reader: impl Read,
+        path: &Path,
+        out: &mut impl Write,
+        skip_binary: bool,
+    ) -> io::Result<()> {
+        if !self.search_zip {
+            return self.search_bytes(reader, path, out, skip_binary);
+        }
+        let start = peek(&mut reader, decompress::MAGIC_LEN)?;
+        let format = Format::detect(&start);
+        let reader = io::Cursor::new(start).chain(reader);
+        match format {
+            Some(format) => {
+                self.search_bytes(Decompressor::new(format, reader)?, path, out, skip_binary)
+            }
+            None => self.search_bytes(reader, path, out, skip_binary),
+        }
+    }
+
+    // Skips the input if it looks binary and `skip_binary` says we should, and otherwise works out what it's encoded in. Most of the time it's UTF-8 and goes straight through, anything else gets decoded into UTF-8 on the way in.
+    fn search_bytes(
+        &self,
+        mut reader: impl Read,
+        path: &Path,
+        out: &mut impl Write,
+        skip_binary: bool,
+    ) -> io::Result<()> {
+        // Three bytes is enough to spot any BOM, but telling whether something is binary takes more. We only look further when we have to, since on a pipe every byte we wait for is a line that doesn't get searched yet.
+        let mut start = peek(&mut reader, if skip_binary { BINARY_PEEK } else { 3 })?;
+        let decoding = transcode::sniff(&start, self.encoding);
+        let binary = match decoding {
+            Some((encoding, _)) => transcode::looks_binary_before_decoding(&start, encoding),
+            None => looks_binary(&start),
+        };
+        if skip_binary && binary {
+            return Ok(());
+        }
+
+        let Some((encoding, bom_len)) = decoding else {
+            return self.search_text(io::Cursor::new(start).chain(reader), path, out, UTF_8, None);
+        };
+        let rest = io::Cursor::new(start.split_off(bom_len)).chain(reader);
+        let mut transcoder = Transcoder::new(rest, encoding, bom_len, self.lossy);
+        let offsets = self
+            .track_offsets
+            .then(|| transcoder.track_offsets(self.searcher.before_context));
+        self.search_text(transcoder, path, out, encoding, offsets.as_deref())
+    }
+
+    // Everything here reads a line at a time rather than loading the whole input first, so it works just as well on a huge file or a never-ending pipe. -l can even stop the moment it sees the first selected line.
+    //
+    // `encoding` is what the input was decoded from, and `offsets` (when it was decoded) maps byte offsets in the UTF-8 back to the original file.
+    fn search_text(
+        &self,
+        reader: impl Read,
+        path: &Path,
+        out: &mut impl Write,
+        encoding: &'static Encoding,
+        offsets: Option<&RefCell<Offsets>>,
+    ) -> io::Result<()> {
+        let matcher = self.matcher.as_ref();
+        let shown_path = if self.with_path { Some(path) } else { None };
+        let original =
+            |offset| offsets.map_or(offset, |offsets| offsets.borrow_mut().original(offset));
+
+        match self.output_mode {
+            OutputMode::Json => {
+                json::write_begin(out, path).map_err(output_error)?;
+                let mut stats = json::Stats::default();
+                let mut written = Ok(());
+                let searched = self.searcher.search_raw(matcher, reader, |mut found| {
+                    found.byte_offset = original(found.byte_offset);
+                    stats.matched_lines += 1;
+                    stats.matches += found.ranges.len();
+                    written = json::write_match(out, path, &found);
+                    written.is_ok()
+                })?;
+                stats.bytes_searched = original(searched);
+                written.map_err(output_error)?;
+                json::write_end(out, path, &stats).map_err(output_error)?;
+                self.summary.lock().unwrap().add_file(&stats);
+            }
+            OutputMode::Lines => {
+                // The callback can't return an error itself, so we hold on to it and stop the search there.
+                let mut written = Ok(());
+                self.searcher.search_reader(matcher, reader, |line| {
+                    written = match line {
+                        // This one names the file even when there's only one, since "Binary file matches" on its own doesn't say much.
+                        SearchLine::BinaryMatch => self.printer.print_binary_match(out, path),
+                        line => self.printer.print_line(
+                            out,
+                            shown_path,
+                            &with_original_offset(line, original),
+                            matcher,
+                            encoding,
+                        ),
+                    };
+                    written.is_ok()
+                })?;
+                written.map_err(output_error)?;
+            }
+            OutputMode::Count => {
+                let count = self.searcher.count(matcher, reader)?;
+                self.printer
+                    .print_count(out, shown_path, count)
+                    .map_err(output_error)?;
+            }
+            OutputMode::FilesWithMatches => {
+                if self.searcher.has_match(matcher, reader)? {
+                    self.printer.print_path(out, path).map_err(output_error)?;
+                }
+            }
+            OutputMode::FilesWithoutMatch => {
+                if !self.searcher.has_match(matcher, reader)? {
+                    self.printer.print_path(out, path).map_err(output_error)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// An error writing our results, as opposed to reading a file. It travels inside an io::Error like every other problem, keeping the same kind (so BrokenPipe is still BrokenPipe), and run_job() unwraps it to tell the two apart.
+#[derive(Debug)]
+struct OutputError(io::Error);
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for OutputError {}
+
+fn output_error(err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), OutputError(err))
+}
+
+fn is_output_error(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|inner| inner.is::<OutputError>())
+}
+
+// A reader that fails once `stop` is set. Searches like -c and -l don't write anything until they're done with a file, so this is what stops them partway through.
+struct UntilStopped<'a, R> {
+    reader: R,
+    stop: &'a AtomicBool,
+}
+
+impl<'a, R> UntilStopped<'a, R> {
+    fn new(reader: R, stop: &'a AtomicBool) -> UntilStopped<'a, R> {
+        UntilStopped { reader, stop }
+    }
+}
+
+impl<R: Read> Read for UntilStopped<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.stop.load(Ordering::Relaxed) {
+            // It's the output that went wrong, not the file, so run_job() shouldn't report it as a problem with the file.
+            return Err(output_error(io::Error::other("writing the output failed")));
+        }
+        self.reader.read(buf)
+    }
+}
+
+// Reads up to `length` bytes from the start of `reader`, fewer only if it runs out first.
+fn peek(reader: &mut impl Read, length: usize) -> io::Result<Vec<u8>> {
+    let mut start = Vec::with_capacity(length);
+    reader.take(length as u64).read_to_end(&mut start)?;
+    Ok(start)
+}
+
+// The same line with its byte offset moved from the UTF-8 we searched to the file it came from.
+fn with_original_offset<'a>(
+    line: SearchLine<'a>,
+    original: impl Fn(usize) -> usize,
+) -> SearchLine<'a> {
+    match line {
+        SearchLine::Match(found) => SearchLine::Match(Match {
+            byte_offset: original(found.byte_offset),
+            ..found
+        }),
+        SearchLine::Context {
+            line_number,
+            byte_offset,
+            line,
+        } => SearchLine::Context {
+            line_number,
+            byte_offset: original(byte_offset),
+            line,
+        },
+        line => line,
+    }
+}
+
+pub fn search<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
     // the lifetime parameters specify which argument lifetime is connected to the lifetime of the return value. In this case, we indicate that the returned vector should contain string slices that reference slices of the argument contents (rather than the argument query).
 
     // Let's rewrite this code to use our iterator adaptors instead.
@@ -75,13 +907,16 @@ pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
     // results
 
     // This is much more concise now, and also lets us avoid having a mutable intermediate results vector. This leans more into functional programming style, which tends to prefer a minimal amount of mutable state to make code clearer.
-    contents
-        .lines()
-        .filter(|line| line.contains(query.trim()))
-        .collect()
+    // contents
+    //     .lines()
+    //     .filter(|line| line.contains(query.trim()))
+    //     .collect()
+
+    // Now that we have matchers, the plain search is just the literal one plugged into search_with().
+    search_with(&LiteralMatcher::new(query), contents)
 }
 
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
     // let's replicate the search() function's new design using iterators
     // let query = query.to_lowercase();
     // let mut results = Vec::new();
@@ -93,12 +928,52 @@ pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a st
     // }
     // results
 
-    contents
-        .lines()
-        .filter(|line| line.to_lowercase().contains(query.trim()))
+    // contents
+    //     .lines()
+    //     .filter(|line| line.to_lowercase().contains(query.trim()))
+    //     .collect()
+
+    search_with(&CaseInsensitiveMatcher::new(query), contents)
+}
+
+// The same iterator chain as before, except the decision of what counts as a match now belongs to whichever Matcher we get handed, and instead of the bare line we hand back a Match that remembers where it was found.
+pub fn search_with<'a>(matcher: &dyn Matcher, contents: &'a str) -> Vec<Match<'a>> {
+    lines_with_offsets(contents)
+        .filter_map(|(line_number, byte_offset, line)| {
+            let ranges = matcher.find_iter(line);
+            if ranges.is_empty() {
+                return None;
+            }
+            Some(Match {
+                line_number,
+                byte_offset,
+                ranges,
+                line,
+            })
+        })
         .collect()
 }
 
+// contents.lines() throws away where each line started, so we split on '\n' ourselves and keep a running byte count. The line endings get stripped the same way lines() does it, '\r\n' included.
+fn lines_with_offsets(contents: &str) -> impl Iterator<Item = (usize, usize, &str)> {
+    contents
+        .split_inclusive('\n')
+        .scan(0, |offset, raw| {
+            let start = *offset;
+            *offset += raw.len();
+            Some((start, trim_line_ending(raw)))
+        })
+        .enumerate()
+        .map(|(index, (start, line))| (index + 1, start, line))
+}
+
+fn trim_line_ending(raw: &str) -> &str {
+    match raw.strip_suffix('\n') {
+        Some(line) => line.strip_suffix('\r').unwrap_or(line),
+        None => raw,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,12 +982,20 @@ mod tests {
     fn case_sensitive() {
         let query = "duct";
         let contents = "\
-        Rust:
-        safe, fast, productive.
-        Pick three.
-        Duct tape.";
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape.";
 
-        assert_eq!(vec!["safe, fast, productive."], search(query, contents));
+        assert_eq!(
+            vec![Match {
+                line_number: 2,
+                byte_offset: 6,
+                ranges: vec![Range { start: 15, end: 19 }],
+                line: "safe, fast, productive.",
+            }],
+            search(query, contents)
+        );
     }
 
     #[test]
@@ -124,9 +1007,414 @@ safe, fast, productive.
 Pick three.
 Trust me.";
 
+        let lines: Vec<&str> = search_case_insensitive(query, contents)
+            .iter()
+            .map(|found| found.line)
+            .collect();
+
+        assert_eq!(vec!["Rust:", "Trust me."], lines);
+
+        // Case folding has to work beyond ASCII too, and in both directions.
+        let contents = "\
+Die STRASSE ist lang.
+Eine Straße in İstanbul.
+ISTANBUL";
+
+        let found = search_case_insensitive("straße", contents);
+        assert_eq!(
+            vec![1, 2],
+            found.iter().map(|m| m.line_number).collect::<Vec<_>>()
+        );
+        // Ranges point into the original line, so the 2-byte "ß" is covered in full.
+        assert_eq!(vec![Range { start: 5, end: 12 }], found[1].ranges);
+
+        let found = search_case_insensitive("istanbul", contents);
+        assert_eq!(
+            vec![2, 3],
+            found.iter().map(|m| m.line_number).collect::<Vec<_>>()
+        );
+        assert_eq!("İstanbul", &found[0].line[found[0].ranges[0].clone()]);
+    }
+
+    #[test]
+    fn regex_search() {
+        let matcher = RegexMatcher::new(r"^fn\s+\w+", false).unwrap();
+        let contents = "\
+fn main() {
+    helper();
+}
+fn helper() {}";
+
+        let lines: Vec<&str> = search_with(&matcher, contents)
+            .iter()
+            .map(|found| found.line)
+            .collect();
+
+        assert_eq!(vec!["fn main() {", "fn helper() {}"], lines);
+    }
+
+    #[test]
+    fn matches_know_where_they_are() {
+        let contents = "one fish\r\ntwo fish\nred fish fish";
+        let found = search("fish", contents);
+
+        assert_eq!(
+            vec![(1, 0), (2, 10), (3, 19)],
+            found
+                .iter()
+                .map(|found| (found.line_number, found.byte_offset))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!("two fish", found[1].line);
+        assert_eq!(vec![4..8, 9..13], found[2].ranges);
+    }
+
+    #[test]
+    fn build_rejects_invalid_regex() {
+        let args = ["cli", "-E", "error(\\d{3}", "log.txt"].map(String::from);
+        let err = Config::build(args.into_iter()).err().unwrap();
+
+        assert!(
+            matches!(&err, ConfigError::InvalidRegex { pattern, .. } if pattern == "error(\\d{3}")
+        );
+        assert!(err
+            .to_string()
+            .starts_with("Invalid regular expression 'error(\\d{3}'"));
+    }
+
+    #[test]
+    fn build_accepts_regex_flag_anywhere() {
+        let args = ["cli", "error\\d{3}", "log.txt", "--regex"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+
+        assert!(config.regex);
+        assert_eq!(vec!["log.txt"], config.file_paths);
+    }
+
+    #[test]
+    fn build_collects_every_path() {
+        let args = ["cli", "needle", "a.txt", "b.txt", "c.txt"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+
+        assert_eq!(vec!["needle"], config.patterns);
+        assert_eq!(vec!["a.txt", "b.txt", "c.txt"], config.file_paths);
+    }
+
+    #[test]
+    fn build_reads_position_flags() {
+        let args = ["cli", "-n", "needle", "--column", "a.txt", "-b"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+
+        assert!(config.line_number && config.column && config.byte_offset);
+        assert_eq!(vec!["a.txt"], config.file_paths);
+    }
+
+    #[test]
+    fn build_reads_context_flags() {
+        let args = ["cli", "-C", "3", "-A", "1", "needle", "a.txt"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+
+        assert_eq!(3, config.before_context);
+        assert_eq!(1, config.after_context);
+    }
+
+    #[test]
+    fn build_rejects_bad_context() {
+        let args = ["cli", "needle", "a.txt", "-B", "some"].map(String::from);
+
+        assert_eq!(
+            Some(ConfigError::InvalidValue {
+                flag: String::from("-B"),
+                value: String::from("some"),
+                expected: "a number",
+            }),
+            Config::build(args.into_iter()).err()
+        );
+    }
+
+    #[test]
+    fn build_uses_the_last_output_mode() {
+        let args = ["cli", "-c", "needle", "a.txt", "-L"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+
+        assert_eq!(OutputMode::FilesWithoutMatch, config.output_mode);
+    }
+
+    #[test]
+    fn build_understands_flag_spellings() {
+        let args = ["cli", "-inA2", "--context=4", "--", "-v", "notes.txt"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+
+        assert!(config.ignore_case && config.line_number && !config.invert_match);
+        assert_eq!((4, 2), (config.before_context, config.after_context));
+        assert_eq!(vec!["-v"], config.patterns);
+        assert_eq!(vec!["notes.txt"], config.file_paths);
+    }
+
+    #[test]
+    fn build_names_unknown_flags() {
+        let args = ["cli", "needle", "--colorful"].map(String::from);
+
+        assert_eq!(
+            Some(ConfigError::UnknownFlag(String::from("--colorful"))),
+            Config::build(args.into_iter()).err()
+        );
+    }
+
+    #[test]
+    fn build_stops_for_help_and_version() {
+        let args = ["cli", "needle", "--help"].map(String::from);
+        assert_eq!(
+            Some(ConfigError::Help),
+            Config::build(args.into_iter()).err()
+        );
+
+        let args = ["cli", "-V"].map(String::from);
+        assert_eq!(
+            Some(ConfigError::Version),
+            Config::build(args.into_iter()).err()
+        );
+    }
+
+    #[test]
+    fn build_reads_thread_options() {
+        let args = ["cli", "-j4", "--sort", "path", "needle", "src"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!((4, Sort::Path), (config.threads, config.sort));
+
+        let args = ["cli", "needle", "--sort=size"].map(String::from);
+        assert!(matches!(
+            Config::build(args.into_iter()),
+            Err(ConfigError::InvalidValue { value, .. }) if value == "size"
+        ));
+    }
+
+    #[test]
+    fn build_reads_boundary_flags() {
+        let build = |args: &[&str]| Config::build(args.iter().map(|arg| arg.to_string())).unwrap();
+
+        assert_eq!(Boundary::Word, build(&["cli", "-w", "duct"]).boundary);
+        assert_eq!(Boundary::Line, build(&["cli", "-x", "-w", "duct"]).boundary);
+
+        // "duct" is inside "productive", which case_sensitive relies on, but it isn't a word of its own.
+        let contents = "safe, fast, productive.\nduct tape";
+        let config = build(&["cli", "-iw", "DUCT"]);
+        let matcher = config.matcher().unwrap();
+        assert_eq!(1, search_with(matcher.as_ref(), contents).len());
+    }
+
+    #[test]
+    fn build_collects_patterns() {
+        let path = env::temp_dir().join(format!("cli-patterns-{}.txt", std::process::id()));
+        fs::write(&path, "fish\r\nbird\n").unwrap();
+        let args = [
+            "cli",
+            "-e",
+            "cat",
+            &format!("--file={}", path.display()),
+            "notes.txt",
+        ]
+        .map(String::from);
+        let config = Config::build(args.into_iter());
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        // With -e or -f there's no query in the positional arguments, so notes.txt is a path.
+        assert_eq!(vec!["cat", "fish", "bird"], config.patterns);
+        assert_eq!(vec!["notes.txt"], config.file_paths);
+        let matcher = config.matcher().unwrap();
+        assert_eq!(
+            2,
+            search_with(matcher.as_ref(), "a cat\na dog\na bird").len()
+        );
+
+        let args = ["cli", "-f", "/no/such/patterns.txt"].map(String::from);
+        assert!(matches!(
+            Config::build(args.into_iter()),
+            Err(ConfigError::PatternFile { path, .. }) if path == "/no/such/patterns.txt"
+        ));
+    }
+
+    #[test]
+    fn build_reads_color_choice() {
+        let args = ["cli", "--color=always", "needle"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(ColorChoice::Always, config.color);
+
+        let args = ["cli", "--colour", "sometimes", "needle"].map(String::from);
+        assert!(matches!(
+            Config::build(args.into_iter()),
+            Err(ConfigError::InvalidValue { value, .. }) if value == "sometimes"
+        ));
+    }
+
+    #[test]
+    fn build_reads_binary_flag() {
+        for flag in ["-a", "--binary", "--text"] {
+            let args = ["cli", flag, "needle"].map(String::from);
+            assert!(Config::build(args.into_iter()).unwrap().binary_as_text);
+        }
+        let args = ["cli", "needle"].map(String::from);
+        assert!(!Config::build(args.into_iter()).unwrap().binary_as_text);
+    }
+
+    #[test]
+    fn build_reads_walk_options() {
+        let args = ["cli", "--hidden", "--no-ignore", "needle"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert!(config.walk.hidden && config.walk.no_ignore);
+
+        let args = ["cli", "needle"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(WalkOptions::default(), config.walk);
+    }
+
+    #[test]
+    fn build_reads_globs_and_types() {
+        let args = [
+            "cli",
+            "-g",
+            "*.rs",
+            "--glob=!target/",
+            "--type-add",
+            "proto:*.proto",
+            "-tproto",
+            "--type-not",
+            "md",
+            "needle",
+        ]
+        .map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(vec!["*.rs", "!target/"], config.walk.globs);
+        assert_eq!(vec!["*.proto"], config.walk.types);
+        assert_eq!(vec!["*.md", "*.markdown"], config.walk.types_not);
+
+        let args = ["cli", "--type", "klingon", "needle"].map(String::from);
+        assert!(matches!(
+            Config::build(args.into_iter()),
+            Err(ConfigError::InvalidValue { value, .. }) if value == "klingon"
+        ));
+        let args = ["cli", "-g", "[z-a", "needle"].map(String::from);
+        assert!(matches!(
+            Config::build(args.into_iter()),
+            Err(ConfigError::InvalidValue { flag, .. }) if flag == "-g"
+        ));
+    }
+
+    #[test]
+    fn build_reads_search_zip() {
+        let args = ["cli", "-zi", "needle"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert!(config.search_zip && config.ignore_case);
+
+        let args = ["cli", "needle"].map(String::from);
+        assert!(!Config::build(args.into_iter()).unwrap().search_zip);
+    }
+
+    #[test]
+    fn build_reads_replace_and_in_place() {
+        let args = [
+            "cli",
+            "-E",
+            "--replace",
+            "$1",
+            "(a)",
+            "--in-place=.bak",
+            "src",
+        ]
+        .map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(Some(String::from("$1")), config.replace);
+        assert_eq!(
+            Some(InPlace {
+                backup: Some(String::from(".bak")),
+                dry_run: false,
+            }),
+            config.in_place
+        );
+        assert_eq!(vec!["src"], config.file_paths);
+
+        let args = ["cli", "--replace=b", "--dry-run", "a"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(
+            Some(InPlace {
+                backup: None,
+                dry_run: true,
+            }),
+            config.in_place
+        );
+
+        let args = ["cli", "--in-place", "a"].map(String::from);
+        assert_eq!(
+            Some(ConfigError::NeedsFlag {
+                flag: String::from("--in-place"),
+                needs: "--replace",
+            }),
+            Config::build(args.into_iter()).err()
+        );
+
+        let args = ["cli", "--replace=b", "--dry-run", "-c", "a"].map(String::from);
         assert_eq!(
-            vec!["Rust:", "Trust me."],
-            search_case_insensitive(query, contents)
+            Some(ConfigError::Conflict {
+                flag: "--dry-run",
+                other: "--count",
+            }),
+            Config::build(args.into_iter()).err()
         );
+        let args = ["cli", "--replace=b", "--in-place", "-v", "a"].map(String::from);
+        assert_eq!(
+            Some(ConfigError::Conflict {
+                flag: "--in-place",
+                other: "--invert-match",
+            }),
+            Config::build(args.into_iter()).err()
+        );
+        for (given, other) in [
+            ("-o", "--only-matching"),
+            ("-C2", "--context"),
+            ("-n", "--line-number"),
+            ("--color=never", "--color"),
+        ] {
+            let args = ["cli", "--replace=b", "--in-place", given, "a"].map(String::from);
+            assert_eq!(
+                Some(ConfigError::Conflict {
+                    flag: "--in-place",
+                    other,
+                }),
+                Config::build(args.into_iter()).err()
+            );
+        }
+
+        // -r is left for grep's --recursive, which we don't need a flag for.
+        let args = ["cli", "-r", "foo", "src"].map(String::from);
+        assert_eq!(
+            Some(ConfigError::UnknownFlag(String::from("-r"))),
+            Config::build(args.into_iter()).err()
+        );
+    }
+
+    #[test]
+    fn build_reads_encoding() {
+        let args = ["cli", "--encoding=latin1", "--lossy", "needle"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+        assert_eq!(Some(encoding_rs::WINDOWS_1252), config.encoding);
+        assert!(config.lossy);
+
+        let args = ["cli", "--encoding", "auto", "needle"].map(String::from);
+        assert_eq!(None, Config::build(args.into_iter()).unwrap().encoding);
+
+        let args = ["cli", "--encoding", "klingon", "needle"].map(String::from);
+        assert!(matches!(
+            Config::build(args.into_iter()),
+            Err(ConfigError::InvalidValue { value, .. }) if value == "klingon"
+        ));
+    }
+
+    #[test]
+    fn build_reads_stdin_without_a_path() {
+        let args = ["cli", "needle"].map(String::from);
+        let config = Config::build(args.into_iter()).unwrap();
+
+        assert_eq!(vec!["-"], config.file_paths);
     }
 }
@@ -0,0 +1,157 @@
+use std::io::{self, Read};
+
+// How much we read at a time. Big enough that we're not constantly asking the OS for more, small enough that searching a huge file doesn't cost a huge amount of memory.
+pub const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+// Reads lines out of any reader using one fixed-size buffer that gets reused over and over. BufRead::read_line would do the reading for us, but it appends into a String that we don't control, and a file with no newlines in it would end up entirely in memory.
+//
+// The buffer only grows if a single line is longer than the whole buffer, since we need the complete line to search it. So memory use depends on the longest line, never on the size of the file.
+pub struct LineReader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    // The bytes we've read but not handed out yet live in buffer[start..end].
+    start: usize,
+    end: usize,
+    // How far past `start` we've already looked for a '\n', so a long line isn't rescanned from the beginning every time more of it arrives.
+    searched: usize,
+    eof: bool,
+}
+
+impl<R: Read> LineReader<R> {
+    pub fn new(reader: R) -> LineReader<R> {
+        LineReader::with_capacity(reader, DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(reader: R, capacity: usize) -> LineReader<R> {
+        LineReader {
+            reader,
+            buffer: vec![0; capacity.max(1)],
+            start: 0,
+            end: 0,
+            searched: 0,
+            eof: false,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    // The next line, including its '\n' if it has one, or None once the reader is empty. The slice borrows our buffer, so it has to be dropped before asking for the next line.
+    pub fn next_line(&mut self) -> io::Result<Option<&[u8]>> {
+        loop {
+            let unsearched = &self.buffer[self.start + self.searched..self.end];
+            if let Some(newline) = memchr::memchr(b'\n', unsearched) {
+                let line_end = self.start + self.searched + newline + 1;
+                return Ok(Some(self.take_until(line_end)));
+            }
+            self.searched = self.end - self.start;
+
+            if self.eof {
+                // Whatever is left is a last line without a '\n' at the end.
+                if self.start == self.end {
+                    return Ok(None);
+                }
+                return Ok(Some(self.take_until(self.end)));
+            }
+
+            self.fill()?;
+        }
+    }
+
+    // As many complete lines as we have in the buffer, all in one slice, or whatever is left once the reader is empty. This is for searching a lot of lines in one go, rather than one at a time with next_line().
+    pub fn next_chunk(&mut self) -> io::Result<Option<&[u8]>> {
+        loop {
+            let unsearched = &self.buffer[self.start + self.searched..self.end];
+            if let Some(newline) = memchr::memrchr(b'\n', unsearched) {
+                let chunk_end = self.start + self.searched + newline + 1;
+                return Ok(Some(self.take_until(chunk_end)));
+            }
+            self.searched = self.end - self.start;
+
+            if self.eof {
+                if self.start == self.end {
+                    return Ok(None);
+                }
+                return Ok(Some(self.take_until(self.end)));
+            }
+
+            self.fill()?;
+        }
+    }
+
+    fn take_until(&mut self, line_end: usize) -> &[u8] {
+        let line_start = self.start;
+        self.start = line_end;
+        self.searched = 0;
+        &self.buffer[line_start..line_end]
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        // Slide the unfinished line down to the front to make room behind it.
+        if self.start > 0 {
+            self.buffer.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        // Still no room means one line fills the entire buffer, and that's the only time we grow.
+        if self.end == self.buffer.len() {
+            self.buffer.resize(self.buffer.len() * 2, 0);
+        }
+
+        loop {
+            match self.reader.read(&mut self.buffer[self.end..]) {
+                Ok(0) => self.eof = true,
+                Ok(read) => self.end += read,
+                // A read can be interrupted by a signal before it gets anything, in which case we just try again.
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_lines(reader: &mut LineReader<&[u8]>) -> Vec<String> {
+        let mut lines = Vec::new();
+        while let Some(line) = reader.next_line().unwrap() {
+            lines.push(String::from_utf8(line.to_vec()).unwrap());
+        }
+        lines
+    }
+
+    #[test]
+    fn splits_lines_across_refills() {
+        let mut reader = LineReader::with_capacity("one\ntwo\r\nthree".as_bytes(), 4);
+
+        assert_eq!(vec!["one\n", "two\r\n", "three"], all_lines(&mut reader));
+    }
+
+    #[test]
+    fn chunks_end_on_whole_lines() {
+        let mut reader = LineReader::with_capacity("one\ntwo\nthree".as_bytes(), 6);
+        let mut chunks = Vec::new();
+        while let Some(chunk) = reader.next_chunk().unwrap() {
+            chunks.push(String::from_utf8(chunk.to_vec()).unwrap());
+        }
+
+        assert_eq!(vec!["one\n", "two\n", "three"], chunks);
+    }
+
+    #[test]
+    fn only_grows_for_a_line_longer_than_the_buffer() {
+        let contents = format!("short\n{}\nshort\n", "x".repeat(20));
+
+        let mut reader = LineReader::with_capacity(contents.as_bytes(), 32);
+        assert_eq!(3, all_lines(&mut reader).len());
+        assert_eq!(32, reader.capacity());
+
+        let mut reader = LineReader::with_capacity(contents.as_bytes(), 8);
+        assert_eq!(3, all_lines(&mut reader).len());
+        assert_eq!(32, reader.capacity());
+    }
+}
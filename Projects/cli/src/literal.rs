@@ -0,0 +1,39 @@
+use memchr::memmem;
+
+// A substring searcher for plain (case-sensitive) queries, built to run over a whole buffer of many lines at once instead of one line at a time.
+//
+// The searching itself is memchr's memmem, which is Two-Way with a rare-byte prefilter: it picks the bytes of the needle that are least likely to show up in normal text (a "q" rather than an "e", say), uses SIMD instructions to jump straight to the places they appear, and only compares the whole needle there. Two-Way takes over if the prefilter keeps stopping in the wrong places, so even a needle like "aaaa" in a file full of "a"s stays linear. We tried writing Boyer-Moore-Horspool with our own rare-byte table first, and it was several times slower than this on log files, where the letters and digits in a query are usually everywhere.
+pub struct Finder {
+    finder: memmem::Finder<'static>,
+}
+
+impl Finder {
+    pub fn new(needle: &[u8]) -> Finder {
+        Finder {
+            finder: memmem::Finder::new(needle).into_owned(),
+        }
+    }
+
+    pub fn needle(&self) -> &[u8] {
+        self.finder.needle()
+    }
+
+    // Where the needle first shows up in `haystack`, if it does at all.
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        self.finder.find(haystack)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_first_occurrence() {
+        let finder = Finder::new(b"duct");
+        assert_eq!(Some(15), finder.find(b"safe, fast, productive, ductile"));
+        assert_eq!(None, finder.find(b"pro-duc-tive"));
+        assert_eq!(Some(0), Finder::new(b"").find(b"anything"));
+        assert_eq!(None, finder.find(b"duc"));
+    }
+}
@@ -1,6 +1,10 @@
-use std::{env, process};
+use std::{
+    env,
+    io::{self, Write},
+    process,
+};
 
-use cli::Config;
+use cli::{Config, ConfigError};
 
 fn main() {
     // let args: Vec<String> = env::args().collect(); // collect() turns the iterator into a vector containing all the values produced by the iterator.
@@ -13,11 +17,29 @@ fn main() {
 
     // This returns an iterator via env::args(), but we have to change our build() to let this compile, so check out our changes in lib.rs
     let config = Config::build(env::args()).unwrap_or_else(|err| {
+        // Asking for --help or --version isn't a mistake, so those go to stdout and we exit successfully.
+        if let ConfigError::Help | ConfigError::Version = err {
+            // println! panics if stdout has gone away, which `cli --help | head -3` does as soon as head has its lines. That's no reason to fail, so a broken pipe counts as success here just like it does below.
+            match writeln!(io::stdout().lock(), "{err}") {
+                Err(err) if err.kind() != io::ErrorKind::BrokenPipe => {
+                    eprintln!("Application error: {err}");
+                    process::exit(1);
+                }
+                _ => process::exit(0),
+            }
+        }
         eprintln!("Problem parsing arguments: {err}");
+        eprintln!("Try 'cli --help' for more information.");
         process::exit(1);
     });
 
     if let Err(e) = cli::run(config) {
+        // Whatever we were writing to went away, like `cli fish | head -1` once head has its line. Nobody's left to read an error about it, so we just stop.
+        if e.downcast_ref::<io::Error>()
+            .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe)
+        {
+            process::exit(0);
+        }
         eprintln!("Application error: {e}");
         process::exit(1);
     }
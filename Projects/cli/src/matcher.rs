@@ -0,0 +1,581 @@
+use std::ops::Range;
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use regex::{Regex, RegexBuilder};
+use regex_syntax::is_word_character;
+
+use crate::{fold::fold, literal::Finder};
+
+// A Matcher is anything that can tell us where a query shows up inside a single line. Keeping this behind a trait means run() doesn't have to care whether we're doing a plain substring search or a regex one, it just asks the matcher.
+// The Send + Sync supertraits promise that a matcher can be shared between threads, which is how one matcher gets used by every worker when we search files in parallel.
+pub trait Matcher: Send + Sync {
+    // Returns the byte range of the first match that starts at or after `start`.
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>>;
+
+    // Default methods work just like the default trait implementations we wrote in our generics notes: every matcher gets them for free as long as it implements find_at().
+    fn is_match(&self, line: &str) -> bool {
+        self.find_at(line, 0).is_some()
+    }
+
+    // Every non-overlapping match in the line, from left to right.
+    fn find_iter(&self, line: &str) -> Vec<Range<usize>> {
+        find_all(self, line)
+    }
+
+    // Matchers that are just looking for one exact string can hand over a Finder for it, which lets the Searcher skip through a whole buffer at once instead of asking about every line.
+    fn finder(&self) -> Option<&Finder> {
+        None
+    }
+
+    // Which pattern found the match at `range`, counting from 0, for matchers built from several patterns. Everything else only has the one pattern, so it has nothing to add.
+    fn which_pattern(&self, _line: &str, _range: &Range<usize>) -> Option<usize> {
+        None
+    }
+
+    // What --replace puts in place of the match at `range`. Only regexes have capture groups to fill in, so for everything else it's the replacement exactly as given.
+    fn expand(&self, _line: &str, _range: &Range<usize>, replacement: &str) -> String {
+        replacement.to_string()
+    }
+}
+
+// What find_iter() does unless a matcher knows a quicker way: ask find_at() again from the end of each match. It lives out here so matchers that only sometimes know better can fall back on it.
+fn find_all<M: Matcher + ?Sized>(matcher: &M, line: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while let Some(range) = matcher.find_at(line, start) {
+        // An empty match (say, the regex "x*") would find the same spot again and again, so we step over the next character to make sure we always make progress. Once there's no next character, we're done.
+        let next = if range.is_empty() {
+            line[range.end..]
+                .chars()
+                .next()
+                .map(|c| range.end + c.len_utf8())
+        } else {
+            Some(range.end)
+        };
+        ranges.push(range);
+        match next {
+            Some(next) => start = next,
+            None => break,
+        }
+    }
+    ranges
+}
+
+pub struct LiteralMatcher {
+    finder: Finder,
+}
+
+impl LiteralMatcher {
+    pub fn new(query: &str) -> LiteralMatcher {
+        // We trim once up front instead of calling query.trim() for every line like our old filter closure did.
+        LiteralMatcher {
+            finder: Finder::new(query.trim().as_bytes()),
+        }
+    }
+}
+
+impl Matcher for LiteralMatcher {
+    // The needle came from a &str and so did the line, so a match can only start and end on character boundaries.
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        let found = self.finder.find(line.get(start..)?.as_bytes())?;
+        Some(start + found..start + found + self.finder.needle().len())
+    }
+
+    fn finder(&self) -> Option<&Finder> {
+        Some(&self.finder)
+    }
+}
+
+pub struct CaseInsensitiveMatcher {
+    needle: String,
+}
+
+impl CaseInsensitiveMatcher {
+    pub fn new(query: &str) -> CaseInsensitiveMatcher {
+        // Both sides need folding, otherwise a query like "RUST" would never match anything.
+        CaseInsensitiveMatcher {
+            needle: fold(query.trim()).text,
+        }
+    }
+}
+
+impl Matcher for CaseInsensitiveMatcher {
+    // We search the folded line, but the range we hand back is in the original line's bytes, so highlighting and columns line up with what's actually on screen.
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        let rest = line.get(start..)?;
+        let folded = fold(rest);
+        let found = folded.text.find(&self.needle)?;
+        let range = folded.original_range(rest, found..found + self.needle.len());
+        Some(start + range.start..start + range.end)
+    }
+
+    // The default find_iter() would fold the rest of the line again for every match. Folding once and walking through the folded text does the same job.
+    fn find_iter(&self, line: &str) -> Vec<Range<usize>> {
+        let folded = fold(line);
+        let found = folded
+            .text
+            .match_indices(&self.needle)
+            .map(|(found, _)| found..found + self.needle.len());
+        folded.original_ranges(line, found)
+    }
+}
+
+// Looks for many literal patterns at once, for -e and -f. Checking each pattern against every line would make a search for 500 identifiers 500 times slower, so instead we build an Aho-Corasick automaton: one state machine that knows every pattern and finds all of them in a single pass over the line.
+pub struct MultiMatcher {
+    automaton: AhoCorasick,
+    ignore_case: bool,
+    // -w and -x get checked in here rather than by a BoundedMatcher. Several patterns can match at the same spot, and the longest one might not fit where a shorter one does ("-w -e foo -e foo-bar" in "foo-barx"), but a BoundedMatcher would only ever get to see the longest.
+    boundary: Boundary,
+    // How long the longest needle is, in bytes.
+    longest: usize,
+}
+
+impl MultiMatcher {
+    pub fn new(patterns: &[String], ignore_case: bool) -> MultiMatcher {
+        MultiMatcher::with_boundary(patterns, ignore_case, Boundary::Anywhere)
+    }
+
+    pub fn with_boundary(
+        patterns: &[String],
+        ignore_case: bool,
+        boundary: Boundary,
+    ) -> MultiMatcher {
+        // Patterns get trimmed (and folded) the same way a single query does, so a pattern file with trailing spaces or Windows line endings still works.
+        let needles: Vec<String> = patterns
+            .iter()
+            .map(|pattern| {
+                if ignore_case {
+                    fold(pattern.trim()).text
+                } else {
+                    pattern.trim().to_string()
+                }
+            })
+            .collect();
+        // When two patterns match at the same spot, "longest" picks the longer one, so "-e foo -e foobar" finds all of "foobar" rather than just the "foo" part. With a boundary we need to see every match, overlapping ones included, which only the standard kind can do.
+        let match_kind = if boundary == Boundary::Anywhere {
+            MatchKind::LeftmostLongest
+        } else {
+            MatchKind::Standard
+        };
+        let automaton = AhoCorasick::builder()
+            .match_kind(match_kind)
+            .build(&needles)
+            .expect("a literal automaton only fails to build if it's enormous");
+        MultiMatcher {
+            automaton,
+            ignore_case,
+            boundary,
+            longest: needles.iter().map(String::len).max().unwrap_or(0),
+        }
+    }
+
+    // The first match at or after `start`, along with the pattern that found it.
+    fn find_with_pattern(&self, line: &str, start: usize) -> Option<(Range<usize>, usize)> {
+        let rest = line.get(start..)?;
+        // Just like CaseInsensitiveMatcher, we search folded text and map the range back to the original.
+        let folded = self.ignore_case.then(|| fold(rest));
+        let text = folded.as_ref().map_or(rest, |folded| folded.text.as_str());
+        let in_line = |range: Range<usize>| {
+            let range = match &folded {
+                Some(folded) => folded.original_range(rest, range),
+                None => range,
+            };
+            start + range.start..start + range.end
+        };
+
+        if self.boundary == Boundary::Anywhere {
+            let found = self.automaton.find(text)?;
+            return Some((in_line(found.range()), found.pattern().as_usize()));
+        }
+
+        // Of every match that fits the boundary, we want the leftmost, and the longest of those. Overlapping matches come out in the order they end, so once one ends more than `longest` bytes past the start of the best we've got, nothing after it can start any earlier.
+        let mut best: Option<(Range<usize>, Range<usize>, usize)> = None;
+        for found in self.automaton.find_overlapping_iter(text) {
+            if best
+                .as_ref()
+                .is_some_and(|(text_range, _, _)| found.end() > text_range.start + self.longest)
+            {
+                break;
+            }
+            let range = in_line(found.range());
+            if !self.boundary.fits(line, &range) {
+                continue;
+            }
+            let better = best.as_ref().is_none_or(|(text_range, _, _)| {
+                (found.start(), text_range.len()) < (text_range.start, found.len())
+            });
+            if better {
+                best = Some((found.range(), range, found.pattern().as_usize()));
+            }
+        }
+        best.map(|(_, range, pattern)| (range, pattern))
+    }
+}
+
+impl Matcher for MultiMatcher {
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        self.find_with_pattern(line, start).map(|(range, _)| range)
+    }
+
+    fn find_iter(&self, line: &str) -> Vec<Range<usize>> {
+        if self.boundary != Boundary::Anywhere {
+            return find_all(self, line);
+        }
+        if !self.ignore_case {
+            return self
+                .automaton
+                .find_iter(line)
+                .map(|found| found.range())
+                .collect();
+        }
+        let folded = fold(line);
+        let found = self
+            .automaton
+            .find_iter(&folded.text)
+            .map(|found| found.range());
+        folded.original_ranges(line, found)
+    }
+
+    // Searching again from the start of the match finds that same match, and this time we keep the pattern.
+    fn which_pattern(&self, line: &str, range: &Range<usize>) -> Option<usize> {
+        self.find_with_pattern(line, range.start)
+            .filter(|(found, _)| found == range)
+            .map(|(_, pattern)| pattern)
+    }
+}
+
+// How much of the text around a match has to line up for it to count, as picked with -w and -x.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Boundary {
+    // Anywhere at all, so "duct" matches inside "productive".
+    #[default]
+    Anywhere,
+    // Not touching any other word characters on either side (-w).
+    Word,
+    // The whole line and nothing else (-x).
+    Line,
+}
+
+impl Boundary {
+    // Whether `range` in `line` sits where this boundary wants it to.
+    pub fn fits(self, line: &str, range: &Range<usize>) -> bool {
+        match self {
+            Boundary::Anywhere => true,
+            // We ask the regex crate what a word character is, so -w means exactly the same thing with -E as without. That's letters and digits from any script and '_', but also combining marks (the accent in a decomposed "é"), connector punctuation like '‿' and the zero-width joiners.
+            Boundary::Word => {
+                let before = line[..range.start].chars().next_back();
+                let after = line[range.end..].chars().next();
+                !before.is_some_and(is_word_character) && !after.is_some_and(is_word_character)
+            }
+            Boundary::Line => range.start == 0 && range.end == line.len(),
+        }
+    }
+
+    // The regex version. Rather than checking afterwards, we build the boundary into the pattern, so the regex engine can try every way of matching and pick one that fits. The half boundaries only look at one side each, which means a pattern that starts or ends with something like "@" still works, where plain \b would need a word character there.
+    pub fn wrap_pattern(self, pattern: &str) -> String {
+        match self {
+            Boundary::Anywhere => pattern.to_string(),
+            Boundary::Word => format!(r"\b{{start-half}}(?:{pattern})\b{{end-half}}"),
+            Boundary::Line => format!("^(?:{pattern})$"),
+        }
+    }
+}
+
+// Wraps a literal or case-insensitive matcher so it only reports matches that fit a Boundary. Any matcher can be wrapped like this, which is why -w and -x work the same whether we ignore case or not.
+pub struct BoundedMatcher {
+    inner: Box<dyn Matcher>,
+    boundary: Boundary,
+}
+
+impl BoundedMatcher {
+    pub fn new(inner: Box<dyn Matcher>, boundary: Boundary) -> BoundedMatcher {
+        BoundedMatcher { inner, boundary }
+    }
+}
+
+impl Matcher for BoundedMatcher {
+    fn which_pattern(&self, line: &str, range: &Range<usize>) -> Option<usize> {
+        self.inner.which_pattern(line, range)
+    }
+
+    fn expand(&self, line: &str, range: &Range<usize>, replacement: &str) -> String {
+        self.inner.expand(line, range, replacement)
+    }
+
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        let mut from = start;
+        loop {
+            let range = self.inner.find_at(line, from)?;
+            if self.boundary.fits(line, &range) {
+                return Some(range);
+            }
+            // This one didn't fit, so try again one character further along. The next match could overlap this one, think "-w aa" in "aaa aa".
+            from = range.start + line[range.start..].chars().next()?.len_utf8();
+        }
+    }
+}
+
+pub struct RegexMatcher {
+    regex: Regex,
+    // When several patterns were combined into `regex`, each of them on its own, so we can tell which one matched.
+    parts: Vec<Regex>,
+}
+
+impl RegexMatcher {
+    // One difference from the other matchers: with ignore_case the regex crate only does simple case folding, one character to one character, so "ß" won't match "SS" here even though it does without -E. Folding the line first like CaseInsensitiveMatcher does would break any pattern that counts characters, like "stra.e" or "\w{6}".
+    //
+    // Compiling can fail (think unbalanced parentheses), so unlike the other matchers this one hands back a Result with the regex crate's error message, which already points at the offending part of the pattern.
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<RegexMatcher, regex::Error> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .build()?;
+        Ok(RegexMatcher {
+            regex,
+            parts: Vec::new(),
+        })
+    }
+
+    // Matches any of `patterns` with a single regex. The regex engine already compiles alternatives into one automaton that tries them all in one pass, so joining the patterns with | is all the multi-pattern work we need. Each pattern is compiled on its own first, which lets a bad one be reported by itself rather than as part of a giant combined pattern. If that fails, the Err holds the pattern that was wrong.
+    pub fn any_of(
+        patterns: &[String],
+        ignore_case: bool,
+        boundary: Boundary,
+    ) -> Result<RegexMatcher, (String, regex::Error)> {
+        let mut parts = Vec::with_capacity(patterns.len());
+        for pattern in patterns {
+            let part = RegexMatcher::new(pattern, ignore_case)
+                .map_err(|error| (pattern.clone(), error))?;
+            parts.push(part.regex);
+        }
+
+        let joined = patterns
+            .iter()
+            .map(|pattern| format!("(?:{pattern})"))
+            .collect::<Vec<_>>()
+            .join("|");
+        let mut matcher = RegexMatcher::new(&boundary.wrap_pattern(&joined), ignore_case)
+            .map_err(|error| (joined, error))?;
+        // Nothing needs telling apart when there's only one pattern.
+        if parts.len() > 1 {
+            matcher.parts = parts;
+        }
+        Ok(matcher)
+    }
+}
+
+impl Matcher for RegexMatcher {
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        self.regex.find_at(line, start).map(|m| m.range())
+    }
+
+    // $1, ${name} and so on get filled in from the match's capture groups, and $$ is a plain $. We run the regex again from where the match started to get at the groups, since find_at() only kept the range.
+    fn expand(&self, line: &str, range: &Range<usize>, replacement: &str) -> String {
+        let mut expanded = String::new();
+        match self.regex.captures_at(line, range.start) {
+            Some(captures) if captures.get(0).is_some_and(|m| m.range() == *range) => {
+                captures.expand(replacement, &mut expanded)
+            }
+            _ => expanded.push_str(replacement),
+        }
+        expanded
+    }
+
+    // Regexes don't say which alternative matched, so we ask each pattern in turn. One that matches exactly the same text is the best answer, otherwise we settle for one that at least starts in the same place.
+    fn which_pattern(&self, line: &str, range: &Range<usize>) -> Option<usize> {
+        let found: Vec<Option<Range<usize>>> = self
+            .parts
+            .iter()
+            .map(|part| part.find_at(line, range.start).map(|m| m.range()))
+            .collect();
+        found
+            .iter()
+            .position(|found| found.as_ref() == Some(range))
+            .or_else(|| {
+                found.iter().position(|found| {
+                    found
+                        .as_ref()
+                        .is_some_and(|found| found.start == range.start)
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal_finds_offset() {
+        let matcher = LiteralMatcher::new("fast");
+        assert_eq!(Some(6..10), matcher.find_at("safe, fast, productive.", 0));
+        assert_eq!(None, matcher.find_at("safe, fast, productive.", 7));
+    }
+
+    #[test]
+    fn find_iter_collects_every_match() {
+        let matcher = LiteralMatcher::new("ab");
+        assert_eq!(vec![0..2, 4..6], matcher.find_iter("abcdab"));
+
+        let matcher = RegexMatcher::new("x*", false).unwrap();
+        assert_eq!(vec![0..0, 1..2, 2..2], matcher.find_iter("ax"));
+    }
+
+    #[test]
+    fn regex_matches_patterns() {
+        let matcher = RegexMatcher::new(r"^fn\s+\w+", false).unwrap();
+        assert!(matcher.is_match("fn main() {"));
+        assert!(!matcher.is_match("    let f = fn_pointer;"));
+
+        let matcher = RegexMatcher::new(r"error\d{3}", true).unwrap();
+        assert!(matcher.is_match("got ERROR404 from upstream"));
+    }
+
+    #[test]
+    fn bounded_matches_whole_words() {
+        let literal = BoundedMatcher::new(Box::new(LiteralMatcher::new("duct")), Boundary::Word);
+        assert!(!literal.is_match("safe, fast, productive."));
+        assert_eq!(vec![16..20], literal.find_iter("productive, and duct tape"));
+
+        // Word characters aren't just ASCII, so "é" stops "café" from matching "caf".
+        let folded =
+            BoundedMatcher::new(Box::new(CaseInsensitiveMatcher::new("CAF")), Boundary::Word);
+        assert!(!folded.is_match("un café"));
+        assert_eq!(vec![10..13], folded.find_iter("un CAFÉ, caf."));
+
+        // The first "aa" we find is inside "aaa", and the next is stuck to "_", so it takes a few tries to get to the real one.
+        let literal = BoundedMatcher::new(Box::new(LiteralMatcher::new("aa")), Boundary::Word);
+        assert_eq!(vec![8..10], literal.find_iter("aaa aa_ aa"));
+
+        // A combining accent is part of the word it's on, the same as in a regex.
+        let line = "cafe\u{301} cafe";
+        let literal = BoundedMatcher::new(Box::new(LiteralMatcher::new("cafe")), Boundary::Word);
+        let regex = RegexMatcher::any_of(&["cafe".to_string()], false, Boundary::Word).unwrap();
+        assert_eq!(vec![7..11], literal.find_iter(line));
+        assert_eq!(regex.find_iter(line), literal.find_iter(line));
+    }
+
+    #[test]
+    fn bounded_matches_whole_lines() {
+        let matcher = BoundedMatcher::new(
+            Box::new(CaseInsensitiveMatcher::new("straße")),
+            Boundary::Line,
+        );
+        assert!(matcher.is_match("STRASSE"));
+        assert!(!matcher.is_match("Straße 5"));
+    }
+
+    #[test]
+    fn regex_respects_boundaries() {
+        let wrap = |boundary: Boundary| Boundary::wrap_pattern(boundary, "foo|foobar");
+        // Without the boundary in the pattern, "foo" would win and then get thrown out for touching "bar".
+        let matcher = RegexMatcher::new(&wrap(Boundary::Word), false).unwrap();
+        assert_eq!(vec![0..6], matcher.find_iter("foobar"));
+
+        let matcher = RegexMatcher::new(&Boundary::Word.wrap_pattern("@user"), false).unwrap();
+        assert!(matcher.is_match("ping @user now"));
+        assert!(!matcher.is_match("ping @username"));
+
+        let matcher = RegexMatcher::new(&wrap(Boundary::Line), true).unwrap();
+        assert!(matcher.is_match("FOOBAR"));
+        assert!(!matcher.is_match("foobar!"));
+    }
+
+    #[test]
+    fn multi_finds_every_pattern_in_one_pass() {
+        let patterns = ["foo", "foobar", "baz "].map(String::from);
+        let matcher = MultiMatcher::new(&patterns, false);
+
+        let line = "a foobar, a baz and a foo";
+        let ranges = matcher.find_iter(line);
+        assert_eq!(vec![2..8, 12..15, 22..25], ranges);
+        let which: Vec<_> = ranges
+            .iter()
+            .map(|range| matcher.which_pattern(line, range))
+            .collect();
+        assert_eq!(vec![Some(1), Some(2), Some(0)], which);
+    }
+
+    #[test]
+    fn multi_folds_case() {
+        let patterns = ["STRASSE", "Weg"].map(String::from);
+        let matcher = MultiMatcher::new(&patterns, true);
+
+        let line = "Hauptstraße und Waldweg";
+        assert_eq!(vec![5..12, 21..24], matcher.find_iter(line));
+        assert_eq!(Some(0), matcher.which_pattern(line, &(5..12)));
+    }
+
+    #[test]
+    fn multi_tries_every_pattern_against_the_boundary() {
+        let patterns = ["foo", "foo-bar"].map(String::from);
+        let matcher = MultiMatcher::with_boundary(&patterns, false, Boundary::Word);
+        // "foo-bar" is stuck to the "x", but "foo" on its own is a whole word.
+        assert_eq!(vec![0..3], matcher.find_iter("foo-barx"));
+        assert_eq!(Some(0), matcher.which_pattern("foo-barx", &(0..3)));
+        // When both fit, the longer one still wins.
+        assert_eq!(vec![0..7, 8..11], matcher.find_iter("foo-bar foo"));
+
+        let patterns = ["STRASSE", "straße-nord"].map(String::from);
+        let matcher = MultiMatcher::with_boundary(&patterns, true, Boundary::Word);
+        assert_eq!(vec![0..7], matcher.find_iter("Straße-Nordx"));
+
+        let matcher = MultiMatcher::with_boundary(&patterns, false, Boundary::Line);
+        assert_eq!(vec![0..7], matcher.find_iter("STRASSE"));
+        assert!(!matcher.is_match("STRASSE-nord"));
+    }
+
+    #[test]
+    fn matches_inside_one_folded_character_dont_overlap() {
+        // "ß" folds to "ss", so a search for "s" finds it twice, but both are the same "ß" in the line.
+        let line = "Straße";
+        assert_eq!(
+            vec![0..1, 4..6],
+            CaseInsensitiveMatcher::new("s").find_iter(line)
+        );
+        let patterns = ["s", "x"].map(String::from);
+        assert_eq!(
+            vec![0..1, 4..6],
+            MultiMatcher::new(&patterns, true).find_iter(line)
+        );
+    }
+
+    #[test]
+    fn regex_any_of_names_the_pattern() {
+        let patterns = [r"id=\d+", r"user=\w+"].map(String::from);
+        let matcher = RegexMatcher::any_of(&patterns, false, Boundary::Anywhere).unwrap();
+
+        let line = "user=ada id=42";
+        assert_eq!(vec![0..8, 9..14], matcher.find_iter(line));
+        assert_eq!(Some(1), matcher.which_pattern(line, &(0..8)));
+        assert_eq!(Some(0), matcher.which_pattern(line, &(9..14)));
+
+        let patterns = ["fine", "(broken"].map(String::from);
+        let error = RegexMatcher::any_of(&patterns, false, Boundary::Anywhere).err();
+        assert_eq!(
+            Some(String::from("(broken")),
+            error.map(|(pattern, _)| pattern)
+        );
+    }
+
+    #[test]
+    fn expand_fills_in_capture_groups() {
+        let matcher =
+            RegexMatcher::any_of(&[r"(?P<key>\w+)=(\d+)".to_string()], false, Boundary::Word)
+                .unwrap();
+        let line = "a=1 b=22";
+        let expanded: Vec<_> = matcher
+            .find_iter(line)
+            .iter()
+            .map(|range| matcher.expand(line, range, "$2:${key} $$"))
+            .collect();
+        assert_eq!(vec!["1:a $", "22:b $"], expanded);
+
+        // Literal matchers have no groups, so the $ stays as it is.
+        assert_eq!("$1", LiteralMatcher::new("a").expand(line, &(0..1), "$1"));
+    }
+
+    #[test]
+    fn regex_reports_bad_pattern() {
+        assert!(RegexMatcher::new("(unclosed", false).is_err());
+    }
+}
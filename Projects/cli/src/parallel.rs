@@ -0,0 +1,408 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Condvar, Mutex,
+    },
+    thread,
+};
+
+// The order results get printed in when several files are searched at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Sort {
+    // Whichever file finishes first gets printed first. Fastest, but the order can change from run to run.
+    #[default]
+    Unsorted,
+    // The same order a single thread would have used, which is path order since the directory walk is sorted.
+    Path,
+}
+
+// How much output a job can keep while it waits for its turn. Past this it stops and waits, since a file full of matches would otherwise end up in memory all at once.
+const MAX_BUFFERED: usize = 1024 * 1024;
+
+// Runs `search` over every job on `threads` worker threads and writes each job's output to `out`. The jobs are handed out as `jobs` comes up with them, so when it's walking a directory the first file gets searched straight away, rather than once the whole tree has been listed.
+//
+// Only one job at a time gets to write straight to `out`: the next one in output order with --sort, or with no sorting whichever job has something to say first. Every other job keeps its output in a Vec<u8> (up to MAX_BUFFERED) until its turn comes, so the lines from two files can never end up mixed together, and the job we're actually printing doesn't pile up in memory.
+//
+// When writing to `out` fails, `stop` gets set, and the searches are expected to give up as soon as they notice. Nothing else is ever going to be seen, so there's no point searching the rest.
+//
+// `search` returns whether that job failed, and we return whether any of them did.
+pub fn search_in_parallel<T: Send, W: Write + Send>(
+    jobs: impl Iterator<Item = T>,
+    threads: usize,
+    sort: Sort,
+    out: &mut W,
+    stop: &AtomicBool,
+    search: impl Fn(&T, &mut JobOutput<W>) -> io::Result<bool> + Sync,
+) -> io::Result<bool> {
+    // Workers take the next job from here as soon as they're free, so a thread that gets a quick file just moves on to the next one instead of waiting for the others. Each job comes numbered, which is the order --sort prints them in. The channel only holds a few jobs per thread, so the walk can't get far ahead of the searching.
+    let (sender, receiver) = mpsc::sync_channel(threads * 4);
+    let receiver = Mutex::new(receiver);
+    let failed = AtomicBool::new(false);
+    let shared = Shared {
+        out: Mutex::new(out),
+        turns: Mutex::new(Turns::default()),
+        turn_ended: Condvar::new(),
+        sort,
+        stop,
+        error: Mutex::new(None),
+    };
+
+    // A scope lets the threads borrow `search` and everything else from this function, since Rust knows they'll all be joined before the scope ends.
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let (receiver, failed, shared, search) = (&receiver, &failed, &shared, &search);
+            scope.spawn(move || loop {
+                // The lock is only held while waiting for a job, not while searching it.
+                let next = receiver.lock().unwrap().recv();
+                let Ok((index, job)) = next else {
+                    break;
+                };
+                // Once writing has failed there's no point searching anything, but we still take the jobs, so the loop below isn't left stuck on a full channel.
+                if shared.stop.load(Ordering::Relaxed) {
+                    continue;
+                }
+                let mut output = JobOutput {
+                    shared,
+                    index,
+                    direct: false,
+                    buffer: Vec::new(),
+                };
+                match search(&job, &mut output) {
+                    Ok(job_failed) => {
+                        failed.fetch_or(job_failed, Ordering::Relaxed);
+                        output.finish();
+                    }
+                    Err(err) => shared.fail(err),
+                }
+            });
+        }
+
+        // Meanwhile this thread comes up with the jobs, which for a directory means walking it.
+        for job in jobs.enumerate() {
+            if shared.stop.load(Ordering::Relaxed) || sender.send(job).is_err() {
+                break;
+            }
+        }
+        // Dropping the sender lets the workers finish once they've taken the last job.
+        drop(sender);
+    });
+
+    match shared.error.into_inner().unwrap() {
+        Some(err) => Err(err),
+        None => Ok(failed.into_inner()),
+    }
+}
+
+// What the workers share besides the jobs.
+struct Shared<'a, W> {
+    out: Mutex<&'a mut W>,
+    turns: Mutex<Turns>,
+    // Wakes up jobs whose buffer is full, to check whether it's their turn yet.
+    turn_ended: Condvar,
+    sort: Sort,
+    stop: &'a AtomicBool,
+    // The first thing that went wrong writing to `out`, for search_in_parallel() to hand back.
+    error: Mutex<Option<io::Error>>,
+}
+
+// Whose turn it is to write to `out`.
+#[derive(Default)]
+struct Turns {
+    // The job writing to `out` right now. It keeps its turn until it's done, so nobody else's lines can end up in the middle of its own.
+    writing: Option<usize>,
+    // With --sort, the job whose output comes next.
+    next_to_print: usize,
+    // Jobs that finished while it wasn't their turn, keyed by their position in `jobs`.
+    finished: BTreeMap<usize, Vec<u8>>,
+}
+
+impl<W: Write> Shared<'_, W> {
+    // Whether the job at `index` could have the turn to write to `out`: nobody else has it, and with --sort nobody has to go first.
+    fn is_turn_of(&self, turns: &Turns, index: usize) -> bool {
+        turns.writing.is_none() && (self.sort == Sort::Unsorted || turns.next_to_print == index)
+    }
+
+    fn take_turn(&self, index: usize) -> bool {
+        let mut turns = self.turns.lock().unwrap();
+        let free = self.is_turn_of(&turns, index);
+        if free {
+            turns.writing = Some(index);
+        }
+        free
+    }
+
+    // Waits until the job at `index` gets the turn, or returns false if everything was stopped first.
+    fn wait_for_turn(&self, index: usize) -> bool {
+        let mut turns = self.turns.lock().unwrap();
+        loop {
+            if self.stop.load(Ordering::Relaxed) {
+                return false;
+            }
+            if self.is_turn_of(&turns, index) {
+                turns.writing = Some(index);
+                return true;
+            }
+            turns = self.turn_ended.wait(turns).unwrap();
+        }
+    }
+
+    // Gives up the turn, and then writes out any finished jobs that can go now. This only ever happens on the thread holding the turn, so the lock on `turns` is never held while writing and a slow reader on the other end doesn't hold up the other workers.
+    fn end_turn(&self) -> io::Result<()> {
+        loop {
+            let output = {
+                let mut turns = self.turns.lock().unwrap();
+                turns.writing = None;
+                turns.next_to_print += 1;
+                self.turn_ended.notify_all();
+                let next = match self.sort {
+                    Sort::Unsorted => turns.finished.pop_first(),
+                    Sort::Path => {
+                        let next = turns.next_to_print;
+                        turns.finished.remove_entry(&next)
+                    }
+                };
+                let Some((index, output)) = next else {
+                    return Ok(());
+                };
+                turns.writing = Some(index);
+                output
+            };
+            self.write(&output)?;
+        }
+    }
+
+    fn write(&self, bytes: &[u8]) -> io::Result<()> {
+        let written = self.out.lock().unwrap().write_all(bytes);
+        written.map_err(|err| {
+            // The error itself goes back to our caller, and the worker gets one of the same kind to stop its search with.
+            let kind = err.kind();
+            self.fail(err);
+            io::Error::new(kind, "writing the output failed")
+        })
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.out.lock().unwrap().flush()
+    }
+
+    // Stops every search, keeping the first error for search_in_parallel() to return.
+    fn fail(&self, err: io::Error) {
+        self.error.lock().unwrap().get_or_insert(err);
+        self.stop.store(true, Ordering::Relaxed);
+        // Taking the lock first means a job can't miss this between checking `stop` and starting to wait.
+        let _turns = self.turns.lock().unwrap();
+        self.turn_ended.notify_all();
+    }
+}
+
+// Where one job writes its output. Until it's the job's turn that means a buffer, and from then on straight to `out`.
+pub struct JobOutput<'s, 'a, W> {
+    shared: &'s Shared<'a, W>,
+    index: usize,
+    // Whether we've got the turn, and so are writing straight to `out`.
+    direct: bool,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> JobOutput<'_, '_, W> {
+    // Called once the job is done. Either we had the turn and pass it on, or our output waits with the other finished jobs. The only way this can fail is writing to `out`, which has already stopped everything.
+    fn finish(self) {
+        if !self.direct {
+            let mut turns = self.shared.turns.lock().unwrap();
+            if !self.shared.is_turn_of(&turns, self.index) {
+                // With no sorting, a job that had nothing to say has no reason to wait for a turn. With --sort it does, since the jobs after it are waiting on it.
+                if self.shared.sort == Sort::Path || !self.buffer.is_empty() {
+                    turns.finished.insert(self.index, self.buffer);
+                }
+                return;
+            }
+            turns.writing = Some(self.index);
+            drop(turns);
+            if self.shared.write(&self.buffer).is_err() {
+                return;
+            }
+        }
+        let _ = self.shared.end_turn();
+    }
+}
+
+impl<W: Write> Write for JobOutput<'_, '_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Writing the output already failed for another job, so this search should stop too.
+        if self.shared.stop.load(Ordering::Relaxed) {
+            return Err(io::Error::other("writing the output failed"));
+        }
+        if !self.direct {
+            self.direct = if self.buffer.len() < MAX_BUFFERED {
+                self.shared.take_turn(self.index)
+            } else if self.shared.wait_for_turn(self.index) {
+                true
+            } else {
+                return Err(io::Error::other("writing the output failed"));
+            };
+            if self.direct {
+                self.shared.write(&std::mem::take(&mut self.buffer))?;
+            }
+        }
+        if self.direct {
+            self.shared.write(buf)?;
+        } else {
+            self.buffer.extend_from_slice(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.direct {
+            self.shared.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{atomic::AtomicUsize, Arc},
+        time::Duration,
+    };
+
+    // Makes the early jobs slower than the later ones, so without sorting they'd finish (and print) out of order.
+    fn slow_search(job: &usize, output: &mut impl Write) -> io::Result<bool> {
+        thread::sleep(Duration::from_millis(((8 - job) * 5) as u64));
+        for line in 0..3 {
+            writeln!(output, "job {job} line {line}")?;
+        }
+        Ok(*job == 5)
+    }
+
+    // Output that a test can look at while the search is still going.
+    #[derive(Clone, Default)]
+    struct Shown(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shown {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sorted_output_keeps_job_order() {
+        let mut out = Vec::new();
+
+        let stop = AtomicBool::new(false);
+        let failed = search_in_parallel(0..8, 4, Sort::Path, &mut out, &stop, |job, output| {
+            slow_search(job, output)
+        })
+        .unwrap();
+
+        let mut expected = Vec::new();
+        for job in 0..8 {
+            slow_search(&job, &mut expected).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(expected).unwrap(),
+            String::from_utf8(out).unwrap()
+        );
+        assert!(failed);
+    }
+
+    #[test]
+    fn unsorted_output_never_interleaves() {
+        let mut out = Vec::new();
+
+        let stop = AtomicBool::new(false);
+        search_in_parallel(0..8, 4, Sort::Unsorted, &mut out, &stop, |job, output| {
+            slow_search(job, output)
+        })
+        .unwrap();
+
+        // Every job's three lines have to come out together, whatever order the jobs finished in.
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(24, lines.len());
+        for group in lines.chunks(3) {
+            let job = group[0].split(' ').nth(1).unwrap();
+            for (line, text) in group.iter().enumerate() {
+                assert_eq!(format!("job {job} line {line}"), *text);
+            }
+        }
+    }
+
+    #[test]
+    fn the_next_job_writes_straight_through() {
+        let shown = Shown::default();
+
+        let stop = AtomicBool::new(false);
+        search_in_parallel(
+            0..4,
+            4,
+            Sort::Path,
+            &mut shown.clone(),
+            &stop,
+            |job, output| {
+                writeln!(output, "job {job}")?;
+                // The first job's line should show up while it's still going, rather than once it's done. A later job has to wait its turn.
+                thread::sleep(Duration::from_millis(50));
+                let seen = String::from_utf8(shown.0.lock().unwrap().clone()).unwrap();
+                assert_eq!(*job == 0, seen.contains(&format!("job {job}")));
+                Ok(false)
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            "job 0\njob 1\njob 2\njob 3\n",
+            String::from_utf8(shown.0.lock().unwrap().clone()).unwrap()
+        );
+    }
+
+    // Takes a few bytes and then fails, like a pipe whose reader has gone away.
+    struct Closed(usize);
+
+    impl Write for Closed {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0 < buf.len() {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.0 -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stops_when_writing_fails() {
+        let searched = AtomicUsize::new(0);
+
+        let stop = AtomicBool::new(false);
+        let result = search_in_parallel(
+            0..1000,
+            4,
+            Sort::Path,
+            &mut Closed(10),
+            &stop,
+            |job, output| {
+                searched.fetch_add(1, Ordering::Relaxed);
+                thread::sleep(Duration::from_millis(1));
+                writeln!(output, "job {job}")?;
+                Ok(false)
+            },
+        );
+
+        assert_eq!(io::ErrorKind::BrokenPipe, result.unwrap_err().kind());
+        assert!(stop.load(Ordering::Relaxed));
+        assert!(searched.into_inner() < 100);
+    }
+}
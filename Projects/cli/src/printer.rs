@@ -0,0 +1,424 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    ops::Range,
+    path::Path,
+};
+
+use encoding_rs::Encoding;
+
+use crate::{color, replace, transcode, ColorChoice, Colors, Config, Match, Matcher, SearchLine};
+
+// The Printer decides what a result looks like on screen. run() finds the matches, and this is the one place that has to know about flags like --line-number, so the search code doesn't get cluttered with formatting.
+pub struct Printer {
+    line_number: bool,
+    column: bool,
+    byte_offset: bool,
+    // Print each match on its own instead of the whole line (-o).
+    only_matching: bool,
+    // The patterns from -e and -f, kept so -o can say which one matched. Left empty when there's only one, since then there's nothing to tell apart.
+    patterns: Vec<String>,
+    // What to show in place of each match (--replace).
+    replace: Option<String>,
+    // Whether to color anything at all, and if so which colors to use.
+    color: bool,
+    colors: Colors,
+}
+
+impl Printer {
+    pub fn new(config: &Config) -> Printer {
+        Printer {
+            line_number: config.line_number,
+            column: config.column,
+            byte_offset: config.byte_offset,
+            only_matching: config.only_matching,
+            patterns: if config.patterns.len() > 1 {
+                config.patterns.clone()
+            } else {
+                Vec::new()
+            },
+            replace: config.replace.clone(),
+            color: match config.color {
+                ColorChoice::Always => true,
+                ColorChoice::Never => false,
+                ColorChoice::Auto => io::stdout().is_terminal(),
+            },
+            colors: config.colors.clone(),
+        }
+    }
+
+    // Everything gets written to `out` rather than straight to stdout with println!, so that when we search files in parallel each one can be written to its own buffer first and printed in one go.
+    //
+    // `encoding` is the one the file was in before we turned it into UTF-8, which -o needs to give byte offsets in the original file.
+    pub fn print_line(
+        &self,
+        out: &mut impl Write,
+        path: Option<&Path>,
+        line: &SearchLine,
+        matcher: &dyn Matcher,
+        encoding: &'static Encoding,
+    ) -> io::Result<()> {
+        if !self.only_matching {
+            // With --replace it's the line with the matches swapped out that gets printed, and the replacements that get highlighted.
+            if let (Some(replacement), SearchLine::Match(found)) = (&self.replace, line) {
+                let (replaced, ranges) =
+                    replace::replace_matches(matcher, found.line, &found.ranges, replacement);
+                let found = Match {
+                    ranges,
+                    line: &replaced,
+                    ..found.clone()
+                };
+                return writeln!(out, "{}", self.format_match(path, &found));
+            }
+            return writeln!(out, "{}", self.format_line(path, line));
+        }
+        // With -o only the matches themselves are interesting, so context lines and separators are left out.
+        if let SearchLine::Match(found) = line {
+            for occurrence in self.format_occurrences(path, found, matcher, encoding) {
+                writeln!(out, "{occurrence}")?;
+            }
+        }
+        Ok(())
+    }
+
+    // --count output: `path:3`, or just `3` when there's only one file.
+    pub fn print_count(
+        &self,
+        out: &mut impl Write,
+        path: Option<&Path>,
+        count: usize,
+    ) -> io::Result<()> {
+        match path {
+            Some(path) => writeln!(out, "{}:{count}", self.format_path(path)),
+            None => writeln!(out, "{count}"),
+        }
+    }
+
+    // What gets printed instead of the lines of a binary file, which would only mess up the terminal.
+    pub fn print_binary_match(&self, out: &mut impl Write, path: &Path) -> io::Result<()> {
+        writeln!(out, "Binary file {} matches", self.format_path(path))
+    }
+
+    // --files-with-matches and --files-without-match only ever print the name, even for a single file, since the name is the whole answer.
+    pub fn print_path(&self, out: &mut impl Write, path: &Path) -> io::Result<()> {
+        writeln!(out, "{}", self.format_path(path))
+    }
+
+    fn format_line(&self, path: Option<&Path>, line: &SearchLine) -> String {
+        match line {
+            SearchLine::Match(found) => self.format_match(path, found),
+            // Context lines use '-' instead of ':' after each field, the same as grep, so it's easy to tell them apart from the real matches. There's no column to show since nothing matched.
+            SearchLine::Context {
+                line_number,
+                byte_offset,
+                line,
+            } => {
+                let mut output = self.format_fields(path, '-', *line_number, None, *byte_offset);
+                output.push_str(line);
+                output
+            }
+            SearchLine::Separator => String::from("--"),
+            SearchLine::BinaryMatch => String::from("Binary file matches"),
+        }
+    }
+
+    fn format_match(&self, path: Option<&Path>, found: &Match) -> String {
+        // Columns count from 1 like line numbers do. An empty match list can't happen for a Match, but falling back to the start of the line costs nothing.
+        let column = found.ranges.first().map_or(0, |range| range.start) + 1;
+        let mut output = self.format_fields(
+            path,
+            ':',
+            found.line_number,
+            Some(column),
+            found.byte_offset,
+        );
+        output.push_str(&self.highlight(found.line, &found.ranges));
+        output
+    }
+
+    // One line per match for -o. Each gets its own column and byte offset, pointing at the match rather than the start of the line, so you can jump straight to it. Empty matches are skipped since there'd be nothing to show. With several patterns, the one that matched goes after a tab, which keeps it easy to split off with `cut -f2`.
+    fn format_occurrences(
+        &self,
+        path: Option<&Path>,
+        found: &Match,
+        matcher: &dyn Matcher,
+        encoding: &'static Encoding,
+    ) -> Vec<String> {
+        found
+            .ranges
+            .iter()
+            .filter(|range| !range.is_empty())
+            .map(|range| {
+                let mut output = self.format_fields(
+                    path,
+                    ':',
+                    found.line_number,
+                    Some(range.start + 1),
+                    found.byte_offset
+                        + transcode::encoded_len(encoding, &found.line[..range.start]),
+                );
+                let text = match &self.replace {
+                    Some(replacement) => matcher.expand(found.line, range, replacement),
+                    None => found.line[range.clone()].to_string(),
+                };
+                output.push_str(&self.paint(&self.colors.matched, &text));
+                let pattern = matcher
+                    .which_pattern(found.line, range)
+                    .and_then(|index| self.patterns.get(index));
+                if let Some(pattern) = pattern {
+                    output.push('\t');
+                    output.push_str(pattern);
+                }
+                output
+            })
+            .collect()
+    }
+
+    // Builds the start of lines like `src/main.rs:12:5:text`, which is the same shape editors expect for their quickfix lists. Each piece is only included when its flag was given.
+    fn format_fields(
+        &self,
+        path: Option<&Path>,
+        separator: char,
+        line_number: usize,
+        column: Option<usize>,
+        byte_offset: usize,
+    ) -> String {
+        let mut output = String::new();
+
+        if let Some(path) = path {
+            output.push_str(&format!("{}{separator}", self.format_path(path)));
+        }
+        if self.line_number {
+            let line_number = self.paint(&self.colors.line_number, &line_number.to_string());
+            output.push_str(&format!("{line_number}{separator}"));
+        }
+        if let (true, Some(column)) = (self.column, column) {
+            let column = self.paint(&self.colors.column, &column.to_string());
+            output.push_str(&format!("{column}{separator}"));
+        }
+        if self.byte_offset {
+            output.push_str(&format!("{byte_offset}{separator}"));
+        }
+        output
+    }
+
+    // The ranges come from find_iter, so they're already in order and never overlap. We copy the text between them as it is and wrap each match in its color.
+    fn highlight(&self, line: &str, ranges: &[Range<usize>]) -> String {
+        if !self.color {
+            return line.to_string();
+        }
+
+        let mut output = String::new();
+        let mut copied = 0;
+        for range in ranges.iter().filter(|range| !range.is_empty()) {
+            output.push_str(&line[copied..range.start]);
+            output.push_str(&self.paint(&self.colors.matched, &line[range.clone()]));
+            copied = range.end;
+        }
+        output.push_str(&line[copied..]);
+        output
+    }
+
+    fn format_path(&self, path: &Path) -> String {
+        self.paint(&self.colors.path, &path.display().to_string())
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color {
+            color::paint(color, text)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CaseInsensitiveMatcher, LiteralMatcher, MultiMatcher};
+    use encoding_rs::UTF_8;
+
+    // Nothing turned on, so each test only has to spell out the fields it's about.
+    fn base() -> Printer {
+        Printer {
+            line_number: false,
+            column: false,
+            byte_offset: false,
+            only_matching: false,
+            patterns: Vec::new(),
+            replace: None,
+            color: false,
+            colors: Colors::default(),
+        }
+    }
+
+    fn found() -> Match<'static> {
+        Match {
+            line_number: 12,
+            byte_offset: 340,
+            ranges: vec![4..8, 9..13],
+            line: "red fish fish",
+        }
+    }
+
+    #[test]
+    fn plain_line_by_default() {
+        let printer = base();
+
+        assert_eq!("red fish fish", printer.format_match(None, &found()));
+    }
+
+    #[test]
+    fn positions_in_quickfix_order() {
+        let printer = Printer {
+            line_number: true,
+            column: true,
+            byte_offset: true,
+            ..base()
+        };
+
+        assert_eq!(
+            "notes.txt:12:5:340:red fish fish",
+            printer.format_match(Some(Path::new("notes.txt")), &found())
+        );
+    }
+
+    #[test]
+    fn context_lines_use_dashes() {
+        let printer = Printer {
+            line_number: true,
+            column: true,
+            ..base()
+        };
+        let context = SearchLine::Context {
+            line_number: 11,
+            byte_offset: 320,
+            line: "one fish",
+        };
+
+        assert_eq!(
+            "notes.txt-11-one fish",
+            printer.format_line(Some(Path::new("notes.txt")), &context)
+        );
+        assert_eq!("--", printer.format_line(None, &SearchLine::Separator));
+    }
+
+    #[test]
+    fn binary_matches_name_the_file() {
+        let printer = Printer {
+            line_number: true,
+            ..base()
+        };
+        let mut out = Vec::new();
+        printer
+            .print_binary_match(&mut out, Path::new("photo.jpg"))
+            .unwrap();
+
+        assert_eq!(
+            "Binary file photo.jpg matches\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn colors_matches_and_positions() {
+        let printer = Printer {
+            line_number: true,
+            color: true,
+            colors: Colors::parse("match=31:path=35:line=32"),
+            ..base()
+        };
+
+        assert_eq!(
+            "\x1b[35mnotes.txt\x1b[0m:\x1b[32m12\x1b[0m:red \x1b[31mfish\x1b[0m \x1b[31mfish\x1b[0m",
+            printer.format_match(Some(Path::new("notes.txt")), &found())
+        );
+    }
+
+    #[test]
+    fn only_matching_gives_each_match_a_line() {
+        let printer = Printer {
+            line_number: true,
+            column: true,
+            byte_offset: true,
+            only_matching: true,
+            ..base()
+        };
+
+        assert_eq!(
+            vec!["12:5:344:fish", "12:10:349:fish"],
+            printer.format_occurrences(None, &found(), &LiteralMatcher::new("fish"), UTF_8)
+        );
+    }
+
+    #[test]
+    fn only_matching_shows_the_text_as_written() {
+        let printer = Printer {
+            column: true,
+            only_matching: true,
+            ..base()
+        };
+        // Case-insensitive matches come out as they appear in the line, not as the query was typed, and the column after "ß" still counts bytes.
+        let line = "Straße and STRASSE";
+        let found = Match {
+            line_number: 1,
+            byte_offset: 0,
+            ranges: CaseInsensitiveMatcher::new("strasse").find_iter(line),
+            line,
+        };
+
+        assert_eq!(
+            vec!["1:Straße", "13:STRASSE"],
+            printer.format_occurrences(
+                None,
+                &found,
+                &CaseInsensitiveMatcher::new("strasse"),
+                UTF_8
+            )
+        );
+    }
+
+    #[test]
+    fn only_matching_names_the_pattern() {
+        let patterns = vec![String::from("red"), String::from("fish")];
+        let printer = Printer {
+            only_matching: true,
+            patterns: patterns.clone(),
+            ..base()
+        };
+        let matcher = MultiMatcher::new(&patterns, false);
+        let mut found = found();
+        found.ranges = matcher.find_iter(found.line);
+
+        assert_eq!(
+            vec!["red\tred", "fish\tfish", "fish\tfish"],
+            printer.format_occurrences(None, &found, &matcher, UTF_8)
+        );
+    }
+
+    #[test]
+    fn replace_swaps_out_the_matches() {
+        let mut printer = Printer {
+            line_number: true,
+            replace: Some(String::from("cod")),
+            color: true,
+            colors: Colors::parse("match=31:line=32"),
+            ..base()
+        };
+        let print = |printer: &Printer| {
+            let mut out = Vec::new();
+            let line = SearchLine::Match(found());
+            printer
+                .print_line(&mut out, None, &line, &LiteralMatcher::new("fish"), UTF_8)
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            "\x1b[32m12\x1b[0m:red \x1b[31mcod\x1b[0m \x1b[31mcod\x1b[0m\n",
+            print(&printer)
+        );
+        printer.only_matching = true;
+        printer.color = false;
+        assert_eq!("12:cod\n12:cod\n", print(&printer));
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    ffi::OsString,
+    fmt::Write as _,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    ops::Range,
+    path::Path,
+    process,
+};
+
+use crate::{trim_line_ending, Matcher};
+
+// How many unchanged lines the --dry-run diff shows around each change, the same as `diff -u`.
+const CONTEXT: usize = 3;
+
+// What --in-place should do besides rewriting files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InPlace {
+    // Keep a copy of each file as it was, named with this on the end, e.g. ".bak" (--in-place=.bak).
+    pub backup: Option<String>,
+    // Print a diff of what would change and leave the files alone (--dry-run).
+    pub dry_run: bool,
+}
+
+// `line` with every match in `ranges` swapped for `replacement`, and where each replacement ended up in the new line, so the printer can highlight them.
+pub fn replace_matches(
+    matcher: &dyn Matcher,
+    line: &str,
+    ranges: &[Range<usize>],
+    replacement: &str,
+) -> (String, Vec<Range<usize>>) {
+    let mut replaced = String::new();
+    let mut new_ranges = Vec::with_capacity(ranges.len());
+    let mut copied = 0;
+    for range in ranges {
+        replaced.push_str(&line[copied..range.start]);
+        let start = replaced.len();
+        replaced.push_str(&matcher.expand(line, range, replacement));
+        new_ranges.push(start..replaced.len());
+        copied = range.end;
+    }
+    replaced.push_str(&line[copied..]);
+    (replaced, new_ranges)
+}
+
+// A whole file with the replacements made, kept a line at a time next to the original so we can show a diff as well as write it out.
+pub struct Rewrite<'a> {
+    // Each line as it was, line ending included.
+    old: Vec<&'a str>,
+    // What each of those lines turned into. A replacement with a newline in it can turn one line into several, or an empty one can take away the only line there was.
+    new: Vec<String>,
+}
+
+impl<'a> Rewrite<'a> {
+    pub fn new(matcher: &dyn Matcher, contents: &'a str, replacement: &str) -> Rewrite<'a> {
+        let old: Vec<&str> = contents.split_inclusive('\n').collect();
+        let new = old
+            .iter()
+            .map(|raw| {
+                // Line endings are left exactly as they were, '\r\n' included, so a file doesn't change anywhere it didn't match.
+                let line = trim_line_ending(raw);
+                let ranges = matcher.find_iter(line);
+                if ranges.is_empty() {
+                    return raw.to_string();
+                }
+                let (mut replaced, _) = replace_matches(matcher, line, &ranges, replacement);
+                replaced.push_str(&raw[line.len()..]);
+                replaced
+            })
+            .collect();
+        Rewrite { old, new }
+    }
+
+    pub fn changed(&self) -> bool {
+        self.old.iter().zip(&self.new).any(|(old, new)| old != new)
+    }
+
+    pub fn contents(&self) -> String {
+        self.new.concat()
+    }
+
+    // A unified diff from the file as it was to the file as it would be, which `patch -p0` or `git apply` can take as it is. We already know which new lines came from which old ones, so there's no need for a real diff algorithm: every changed line is just taken out and its replacement put in.
+    pub fn diff(&self, path: &Path) -> String {
+        let mut output = format!("--- {0}\n+++ {0}\n", path.display());
+
+        // Changes close enough together that their context would touch go in the same hunk.
+        let mut hunks: Vec<Range<usize>> = Vec::new();
+        for index in (0..self.old.len()).filter(|&index| self.old[index] != self.new[index]) {
+            let start = index.saturating_sub(CONTEXT);
+            let end = (index + 1 + CONTEXT).min(self.old.len());
+            match hunks.last_mut() {
+                Some(hunk) if start <= hunk.end => hunk.end = end,
+                _ => hunks.push(start..end),
+            }
+        }
+
+        // How many lines the new file has before the hunk we're on, counted as we go.
+        let mut counted = 0;
+        let mut new_before = 0;
+        for hunk in hunks {
+            new_before += self.new[counted..hunk.start]
+                .iter()
+                .map(|text| line_count(text))
+                .sum::<usize>();
+            counted = hunk.start;
+            let new_count = self.new[hunk.clone()]
+                .iter()
+                .map(|text| line_count(text))
+                .sum();
+            // write! to a String can't fail.
+            let _ = writeln!(
+                output,
+                "@@ -{} +{} @@",
+                hunk_span(hunk.start, hunk.len()),
+                hunk_span(new_before, new_count)
+            );
+
+            for index in hunk {
+                let (old, new) = (self.old[index], &self.new[index]);
+                if old == new {
+                    push_diff_line(&mut output, ' ', old);
+                    continue;
+                }
+                push_diff_line(&mut output, '-', old);
+                for line in new.split_inclusive('\n') {
+                    push_diff_line(&mut output, '+', line);
+                }
+            }
+        }
+        output
+    }
+}
+
+fn line_count(text: &str) -> usize {
+    text.split_inclusive('\n').count()
+}
+
+// Where a hunk starts and how long it is, the way diff writes it: line numbers count from 1, a count of 1 is left off, and an empty side gives the line just before it.
+fn hunk_span(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        count => format!("{},{count}", start + 1),
+    }
+}
+
+fn push_diff_line(output: &mut String, marker: char, line: &str) {
+    output.push(marker);
+    output.push_str(line);
+    if !line.ends_with('\n') {
+        output.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+// Puts `contents` in place of the file at `path` without there ever being a moment when it's half written: the new contents go to a temporary file next to it, which is then renamed over the top. A rename within one directory is atomic, so anything reading the file sees either all of the old one or all of the new one, and if we get interrupted the original is still there.
+pub fn write_in_place(path: &Path, contents: &str, backup: Option<&str>) -> io::Result<()> {
+    // Renaming over a symlink would replace the link itself, so we edit whatever it points to instead.
+    let path = fs::canonicalize(path)?;
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(io::Error::other("not a file that can be edited"));
+    };
+    let permissions = fs::metadata(&path)?.permissions();
+
+    let mut temp_name = OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp = dir.join(temp_name);
+
+    let written = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+        file.write_all(contents.as_bytes())?;
+        // Without this the edited file would get the default permissions, and a script would stop being executable.
+        file.set_permissions(permissions)?;
+        file.sync_all()?;
+        if let Some(suffix) = backup {
+            let mut backup_name = name.to_os_string();
+            backup_name.push(suffix);
+            fs::copy(&path, dir.join(backup_name))?;
+        }
+        fs::rename(&temp, &path)
+    })();
+    // Whatever went wrong, we don't want to leave the temporary file lying around.
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Boundary, LiteralMatcher, RegexMatcher};
+
+    #[test]
+    fn replaces_every_match_in_a_line() {
+        let matcher =
+            RegexMatcher::any_of(&[r"(\w+)fish".to_string()], false, Boundary::Anywhere).unwrap();
+        let line = "redfish, bluefish";
+        let ranges = matcher.find_iter(line);
+        assert_eq!(
+            (String::from("red cod, blue cod"), vec![0..7, 9..17]),
+            replace_matches(&matcher, line, &ranges, "$1 cod")
+        );
+    }
+
+    #[test]
+    fn keeps_line_endings_and_unmatched_lines() {
+        let contents = "one fish\r\ntwo fish\nred\nblue fish";
+        let rewrite = Rewrite::new(&LiteralMatcher::new("fish"), contents, "cod");
+        assert!(rewrite.changed());
+        assert_eq!("one cod\r\ntwo cod\nred\nblue cod", rewrite.contents());
+
+        let rewrite = Rewrite::new(&LiteralMatcher::new("cod"), contents, "fish");
+        assert!(!rewrite.changed());
+        assert_eq!(contents, rewrite.contents());
+    }
+
+    #[test]
+    fn diffs_changed_lines_with_context() {
+        let contents: String = (1..=12).map(|number| format!("line {number}\n")).collect();
+        let contents = contents + "last line 1";
+        let matcher =
+            RegexMatcher::any_of(&[r"^line (1|2|12)$".to_string()], false, Boundary::Anywhere)
+                .unwrap();
+        let rewrite = Rewrite::new(&matcher, &contents, "first\nsecond");
+
+        assert_eq!(
+            "\
+--- notes.txt
++++ notes.txt
+@@ -1,5 +1,7 @@
+-line 1
++first
++second
+-line 2
++first
++second
+ line 3
+ line 4
+ line 5
+@@ -9,5 +11,6 @@
+ line 9
+ line 10
+ line 11
+-line 12
++first
++second
+ last line 1
+\\ No newline at end of file
+",
+            rewrite.diff(Path::new("notes.txt"))
+        );
+    }
+
+    #[test]
+    fn writes_in_place_with_a_backup() {
+        let dir = std::env::temp_dir().join(format!("cli-replace-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.txt");
+        fs::write(&path, "old\n").unwrap();
+
+        write_in_place(&path, "new\n", Some(".bak")).unwrap();
+        assert_eq!("new\n", fs::read_to_string(&path).unwrap());
+        assert_eq!(
+            "old\n",
+            fs::read_to_string(dir.join("notes.txt.bak")).unwrap()
+        );
+        // Only the file and its backup, no temporary file left behind.
+        assert_eq!(2, fs::read_dir(&dir).unwrap().count());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
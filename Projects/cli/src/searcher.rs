@@ -0,0 +1,760 @@
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    io::{self, Read},
+    ops::Range,
+    str,
+};
+
+use crate::{lines::LineReader, trim_line_ending, Config, Finder, Match, Matcher};
+
+// Everything a search can hand back, in the order it should be shown. Context lines and separators only ever show up when we've asked for context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchLine<'a> {
+    Match(Match<'a>),
+    // A line shown only because it sits near a match.
+    Context {
+        line_number: usize,
+        byte_offset: usize,
+        line: &'a str,
+    },
+    // The `--` that goes between two groups of lines that aren't next to each other.
+    Separator,
+    // Stands in for a selected line once the input has turned out to be binary, since printing the line itself would just spray garbage at the terminal. Nothing else comes after it, one is enough to answer "does it match?".
+    BinaryMatch,
+}
+
+// A selected line from search_raw(). It's the same idea as a Match, except the line is kept as the bytes that were actually in the file, since they might not be UTF-8.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawMatch<'a> {
+    pub line_number: usize,
+    pub byte_offset: usize,
+    // Byte ranges into `bytes`, not into any decoded text.
+    pub ranges: Vec<Range<usize>>,
+    pub bytes: &'a [u8],
+}
+
+// The Searcher walks through the contents line by line and decides which lines to keep. The Matcher decides what a match *is*, while this decides what to do around one.
+#[derive(Debug, Clone, Default)]
+pub struct Searcher {
+    pub before_context: usize,
+    pub after_context: usize,
+    // Selects the lines that *don't* match instead. This lives here rather than in each Matcher, so plain, case-insensitive, regex and any future matchers all get flipped the same way.
+    pub invert: bool,
+    // Treat binary input as text, printing its lines like any others (-a). Without this, a selected line in binary input becomes a BinaryMatch.
+    pub binary_as_text: bool,
+}
+
+impl Searcher {
+    pub fn new(config: &Config) -> Searcher {
+        Searcher {
+            before_context: config.before_context,
+            after_context: config.after_context,
+            invert: config.invert_match,
+            binary_as_text: config.binary_as_text,
+        }
+    }
+
+    // The convenient version for when the whole text is already in memory. It runs the same line-by-line search as search_reader(), then points every line back into `contents` so the results can outlive the search.
+    pub fn search<'a>(&self, matcher: &dyn Matcher, contents: &'a str) -> Vec<SearchLine<'a>> {
+        let mut output = Vec::new();
+        self.search_reader(matcher, contents.as_bytes(), |line| {
+            output.push(borrow_from(contents, line));
+            true
+        })
+        // A &[u8] reader can't fail, and contents is a &str so it's already valid UTF-8.
+        .expect("searching a str can't fail");
+        output
+    }
+
+    // The streaming version: lines are read through a fixed-size buffer and handed to `emit` one at a time, so a file (or a pipe that never ends) never has to fit in memory. Anything that implements Read will do, which includes every BufRead. The lines passed to `emit` only live until it returns, which is why it gets a SearchLine<'_>. `emit` returns whether to keep going, so a caller whose output has gone away (say, into `head -1`) doesn't have to sit through the rest of the input.
+    pub fn search_reader(
+        &self,
+        matcher: &dyn Matcher,
+        reader: impl Read,
+        mut emit: impl FnMut(SearchLine<'_>) -> bool,
+    ) -> io::Result<()> {
+        // Without context, every line we emit is a match, so we don't need to see the others at all.
+        if !self.has_context() {
+            return self.for_each_selected_line(
+                matcher,
+                reader,
+                |line_number, byte_offset, line, binary| {
+                    if binary && !self.binary_as_text {
+                        emit(SearchLine::BinaryMatch);
+                        return false;
+                    }
+                    emit(SearchLine::Match(Match {
+                        line_number,
+                        byte_offset,
+                        ranges: matcher.find_iter(line),
+                        line,
+                    }))
+                },
+            );
+        }
+
+        // The last few lines we skipped over, in case the next line matches and we need them as before-context. A VecDeque lets us push on the back and drop off the front cheaply. We have to copy these lines out, since the reader reuses its buffer for the next one.
+        let mut before: VecDeque<(usize, usize, String)> =
+            VecDeque::with_capacity(self.before_context);
+        let mut after_left = 0;
+        let mut last_printed: Option<usize> = None;
+
+        for_each_line(reader, |line_number, byte_offset, line, binary| {
+            let ranges = matcher.find_iter(line);
+            // When inverted, a selected line has nothing highlighted in it (its ranges stay empty), and the lines that did match become the ones that can show up as context.
+            let selected = ranges.is_empty() == self.invert;
+
+            // Once the input looks binary, context lines would be just as much garbage as matches, so all we're waiting for is the first selected line.
+            if binary && !self.binary_as_text {
+                if selected {
+                    emit(SearchLine::BinaryMatch);
+                }
+                return !selected;
+            }
+
+            if selected {
+                // If there's a gap between what we showed last and what we're about to show, the reader needs a separator to tell the two groups apart. Without any context every line is its own group, so separators would just be noise.
+                let first = before.front().map_or(line_number, |(number, _, _)| *number);
+                if let Some(last) = last_printed {
+                    if self.has_context() && first > last + 1 && !emit(SearchLine::Separator) {
+                        return false;
+                    }
+                }
+
+                for (line_number, byte_offset, line) in before.drain(..) {
+                    let context = SearchLine::Context {
+                        line_number,
+                        byte_offset,
+                        line: &line,
+                    };
+                    if !emit(context) {
+                        return false;
+                    }
+                }
+                let found = SearchLine::Match(Match {
+                    line_number,
+                    byte_offset,
+                    ranges,
+                    line,
+                });
+                if !emit(found) {
+                    return false;
+                }
+                last_printed = Some(line_number);
+                after_left = self.after_context;
+            } else if after_left > 0 {
+                // Still inside the after-context of an earlier match. Because we keep counting down from the *latest* match, overlapping context just merges into one longer group.
+                last_printed = Some(line_number);
+                after_left -= 1;
+                return emit(SearchLine::Context {
+                    line_number,
+                    byte_offset,
+                    line,
+                });
+            } else if self.before_context > 0 {
+                // Once the window is full we recycle the oldest String instead of allocating a new one for every line.
+                let mut saved = if before.len() == self.before_context {
+                    before
+                        .pop_front()
+                        .map(|(_, _, saved)| saved)
+                        .unwrap_or_default()
+                } else {
+                    String::new()
+                };
+                saved.clear();
+                saved.push_str(line);
+                before.push_back((line_number, byte_offset, saved));
+            }
+            true
+        })
+    }
+
+    // Like search_reader() without any context, but lines that aren't UTF-8 are searched instead of stopping the whole file. That suits output that can show any bytes safely, like --json. The matcher sees the line with each bad sequence replaced by U+FFFD, and the ranges get mapped back to the original bytes before they're handed to `emit`, which again returns whether to keep going. Returns how many bytes were read.
+    pub fn search_raw(
+        &self,
+        matcher: &dyn Matcher,
+        reader: impl Read,
+        mut emit: impl FnMut(RawMatch<'_>) -> bool,
+    ) -> io::Result<usize> {
+        for_each_raw_line(reader, |line_number, byte_offset, raw| {
+            let bytes = trim_line_ending_bytes(raw);
+            let ranges = match str::from_utf8(bytes) {
+                Ok(line) => matcher.find_iter(line),
+                Err(_) => {
+                    let lossy = Lossy::decode(bytes);
+                    matcher
+                        .find_iter(&lossy.text)
+                        .into_iter()
+                        .map(|range| lossy.to_raw(range.start)..lossy.to_raw(range.end))
+                        .collect()
+                }
+            };
+
+            if ranges.is_empty() != self.invert {
+                return Ok(true);
+            }
+            Ok(emit(RawMatch {
+                line_number,
+                byte_offset,
+                ranges,
+                bytes,
+            }))
+        })
+    }
+
+    // Whether a single line should be picked, taking --invert-match into account.
+    pub fn is_selected(&self, matcher: &dyn Matcher, line: &str) -> bool {
+        matcher.is_match(line) != self.invert
+    }
+
+    // The number of selected lines, for --count. We only need a yes or no per line here, so there's no point building Match values.
+    pub fn count(&self, matcher: &dyn Matcher, reader: impl Read) -> io::Result<usize> {
+        let mut count = 0;
+        self.for_each_selected_line(matcher, reader, |_, _, _, _| {
+            count += 1;
+            true
+        })?;
+        Ok(count)
+    }
+
+    // Whether any line is selected, for --files-with-matches and --files-without-match. We stop reading at the first selected line since the answer can't change after that.
+    pub fn has_match(&self, matcher: &dyn Matcher, reader: impl Read) -> io::Result<bool> {
+        let mut found = false;
+        self.for_each_selected_line(matcher, reader, |_, _, _, _| {
+            found = true;
+            false
+        })?;
+        Ok(found)
+    }
+
+    // Calls `keep_going` with each selected line until it returns false. This is where we decide between the fast path and looking at every line.
+    fn for_each_selected_line(
+        &self,
+        matcher: &dyn Matcher,
+        reader: impl Read,
+        mut keep_going: impl FnMut(usize, usize, &str, bool) -> bool,
+    ) -> io::Result<()> {
+        if let Some(finder) = self.literal_finder(matcher) {
+            return for_each_literal_line(reader, finder, keep_going);
+        }
+        for_each_line(reader, |line_number, byte_offset, line, binary| {
+            if self.is_selected(matcher, line) {
+                keep_going(line_number, byte_offset, line, binary)
+            } else {
+                true
+            }
+        })
+    }
+
+    // The fast path only works when the lines we want are exactly the lines containing one plain string. Inverting needs every line that *doesn't* contain it, an empty needle is in every line anyway, and a needle with a '\n' in it could never match inside a single line but might match across two in the buffer.
+    fn literal_finder<'m>(&self, matcher: &'m dyn Matcher) -> Option<&'m Finder> {
+        if self.invert {
+            return None;
+        }
+        matcher
+            .finder()
+            .filter(|finder| !finder.needle().is_empty() && !finder.needle().contains(&b'\n'))
+    }
+
+    fn has_context(&self) -> bool {
+        self.before_context > 0 || self.after_context > 0
+    }
+}
+
+// Reads one line at a time through a LineReader, calling `keep_going` with its line number, byte offset and text until it returns false or we run out of input. The last argument says whether the input has looked binary at any point up to and including this line.
+fn for_each_line(
+    reader: impl Read,
+    mut keep_going: impl FnMut(usize, usize, &str, bool) -> bool,
+) -> io::Result<()> {
+    let mut binary = false;
+    for_each_raw_line(reader, |line_number, byte_offset, raw| {
+        let text = decode(raw, &mut binary);
+        Ok(keep_going(
+            line_number,
+            byte_offset,
+            trim_line_ending(&text),
+            binary,
+        ))
+    })?;
+    Ok(())
+}
+
+// Whether some bytes look like they came from a binary file rather than text: they have a NUL byte in them, which text files essentially never do, or they aren't UTF-8. A multi-byte character cut off at the very end doesn't count, since that's what the start of a file looks like if it was cut off in the middle of one.
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    memchr::memchr(0, bytes).is_some()
+        || str::from_utf8(bytes).is_err_and(|err| err.error_len().is_some())
+}
+
+// Turns a line's bytes into text we can search, and notes in `binary` if they looked binary. Anything that isn't UTF-8 becomes U+FFFD, so the rest of the line can still be searched.
+fn decode<'a>(bytes: &'a [u8], binary: &mut bool) -> Cow<'a, str> {
+    if memchr::memchr(0, bytes).is_some() {
+        *binary = true;
+    }
+    let text = String::from_utf8_lossy(bytes);
+    if let Cow::Owned(_) = text {
+        *binary = true;
+    }
+    text
+}
+
+// The fast path for a plain query. Instead of checking every line, we search a whole chunk of lines for the needle and only work out which line we're in once we've found it. Most lines in a big file don't match, so most lines are never looked at on their own. Everything we skip over still gets checked for binary data and has its newlines counted, so line numbers, byte offsets and `binary` come out the same as from for_each_line.
+fn for_each_literal_line(
+    reader: impl Read,
+    finder: &Finder,
+    mut keep_going: impl FnMut(usize, usize, &str, bool) -> bool,
+) -> io::Result<()> {
+    let mut lines = LineReader::new(reader);
+    let mut binary = false;
+    // The number of the last line we've counted, and where the current chunk starts in the file.
+    let mut line_number = 0;
+    let mut chunk_offset = 0;
+    while let Some(chunk) = lines.next_chunk()? {
+        // Where we've got to in this chunk. This is always the start of a line.
+        let mut position = 0;
+        while let Some(found) = finder.find(&chunk[position..]) {
+            let hit = position + found;
+            let line_start = memchr::memrchr(b'\n', &chunk[position..hit])
+                .map_or(position, |i| position + i + 1);
+            let line_end =
+                memchr::memchr(b'\n', &chunk[hit..]).map_or(chunk.len(), |i| hit + i + 1);
+
+            binary |= looks_binary(&chunk[position..line_start]);
+            let text = decode(&chunk[line_start..line_end], &mut binary);
+            line_number += count_lines(&chunk[position..line_start]) + 1;
+            if !keep_going(
+                line_number,
+                chunk_offset + line_start,
+                trim_line_ending(&text),
+                binary,
+            ) {
+                return Ok(());
+            }
+            position = line_end;
+        }
+
+        binary |= looks_binary(&chunk[position..]);
+        line_number += count_lines(&chunk[position..]);
+        chunk_offset += chunk.len();
+    }
+    Ok(())
+}
+
+fn count_lines(bytes: &[u8]) -> usize {
+    memchr::memchr_iter(b'\n', bytes).count()
+}
+
+// The same walk through the lines, but handing over the raw bytes (line ending included) and leaving it to `keep_going` to decide what they mean. Returns how many bytes we got through.
+fn for_each_raw_line(
+    reader: impl Read,
+    mut keep_going: impl FnMut(usize, usize, &[u8]) -> io::Result<bool>,
+) -> io::Result<usize> {
+    let mut lines = LineReader::new(reader);
+    let mut line_number = 0;
+    let mut byte_offset = 0;
+    while let Some(raw) = lines.next_line()? {
+        line_number += 1;
+        if !keep_going(line_number, byte_offset, raw)? {
+            break;
+        }
+        byte_offset += raw.len();
+    }
+    Ok(byte_offset)
+}
+
+fn trim_line_ending_bytes(raw: &[u8]) -> &[u8] {
+    match raw.strip_suffix(b"\n") {
+        Some(line) => line.strip_suffix(b"\r").unwrap_or(line),
+        None => raw,
+    }
+}
+
+// A line that wasn't UTF-8, turned into text the matchers can search. Each bad sequence becomes a single U+FFFD, which is three bytes long however many bytes it replaced, so we remember where each one ends in the text and how far ahead (or behind) of the text the original bytes are from there on.
+struct Lossy {
+    text: String,
+    shifts: Vec<(usize, isize)>,
+}
+
+impl Lossy {
+    fn decode(bytes: &[u8]) -> Lossy {
+        let mut text = String::with_capacity(bytes.len());
+        let mut shifts = Vec::new();
+        let mut shift = 0;
+        for chunk in bytes.utf8_chunks() {
+            text.push_str(chunk.valid());
+            if !chunk.invalid().is_empty() {
+                text.push(char::REPLACEMENT_CHARACTER);
+                shift += chunk.invalid().len() as isize
+                    - char::REPLACEMENT_CHARACTER.len_utf8() as isize;
+                shifts.push((text.len(), shift));
+            }
+        }
+        Lossy { text, shifts }
+    }
+
+    // Ranges from a matcher always land on character boundaries, so an offset can never fall in the middle of a U+FFFD.
+    fn to_raw(&self, offset: usize) -> usize {
+        let shift = self
+            .shifts
+            .iter()
+            .take_while(|(end, _)| *end <= offset)
+            .last()
+            .map_or(0, |(_, shift)| *shift);
+        (offset as isize + shift) as usize
+    }
+}
+
+// Swaps a line that borrows from search_reader()'s buffer for the same slice of `contents`. The byte offset tells us exactly where it lives.
+fn borrow_from<'a>(contents: &'a str, line: SearchLine) -> SearchLine<'a> {
+    let slice = |byte_offset: usize, line: &str| &contents[byte_offset..byte_offset + line.len()];
+    match line {
+        SearchLine::Match(found) => SearchLine::Match(Match {
+            line_number: found.line_number,
+            byte_offset: found.byte_offset,
+            line: slice(found.byte_offset, found.line),
+            ranges: found.ranges,
+        }),
+        SearchLine::Context {
+            line_number,
+            byte_offset,
+            line,
+        } => SearchLine::Context {
+            line_number,
+            byte_offset,
+            line: slice(byte_offset, line),
+        },
+        SearchLine::Separator => SearchLine::Separator,
+        SearchLine::BinaryMatch => SearchLine::BinaryMatch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CaseInsensitiveMatcher, LiteralMatcher, RegexMatcher};
+
+    const CONTENTS: &str = "\
+one
+two match
+three
+four
+five
+six match
+seven
+eight match
+nine";
+
+    // Boils the output down to line numbers, with 0 standing in for a separator, so the tests stay readable.
+    fn numbers(output: &[SearchLine]) -> Vec<usize> {
+        output
+            .iter()
+            .map(|line| match line {
+                SearchLine::Match(found) => found.line_number,
+                SearchLine::Context { line_number, .. } => *line_number,
+                SearchLine::Separator => 0,
+                SearchLine::BinaryMatch => panic!("CONTENTS is all text"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn no_context_means_no_separators() {
+        let searcher = Searcher::default();
+        let output = searcher.search(&LiteralMatcher::new("match"), CONTENTS);
+
+        assert_eq!(vec![2, 6, 8], numbers(&output));
+    }
+
+    #[test]
+    fn after_context() {
+        let searcher = Searcher {
+            before_context: 0,
+            after_context: 1,
+            invert: false,
+            binary_as_text: false,
+        };
+        let output = searcher.search(&LiteralMatcher::new("match"), CONTENTS);
+
+        assert_eq!(vec![2, 3, 0, 6, 7, 8, 9], numbers(&output));
+        assert!(matches!(
+            output[1],
+            SearchLine::Context { line: "three", .. }
+        ));
+    }
+
+    #[test]
+    fn before_context() {
+        let searcher = Searcher {
+            before_context: 2,
+            after_context: 0,
+            invert: false,
+            binary_as_text: false,
+        };
+        let output = searcher.search(&LiteralMatcher::new("match"), CONTENTS);
+
+        assert_eq!(vec![1, 2, 0, 4, 5, 6, 7, 8], numbers(&output));
+    }
+
+    #[test]
+    fn overlapping_context_is_merged() {
+        let searcher = Searcher {
+            before_context: 2,
+            after_context: 2,
+            invert: false,
+            binary_as_text: false,
+        };
+        let output = searcher.search(&CaseInsensitiveMatcher::new("MATCH"), CONTENTS);
+
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8, 9], numbers(&output));
+    }
+
+    #[test]
+    fn invert_selects_the_other_lines() {
+        let searcher = Searcher {
+            invert: true,
+            ..Searcher::default()
+        };
+        let output = searcher.search(&CaseInsensitiveMatcher::new("MATCH"), CONTENTS);
+
+        assert_eq!(vec![1, 3, 4, 5, 7, 9], numbers(&output));
+        assert!(matches!(&output[0], SearchLine::Match(found) if found.ranges.is_empty()));
+    }
+
+    #[test]
+    fn invert_uses_matching_lines_as_context() {
+        let searcher = Searcher {
+            before_context: 0,
+            after_context: 1,
+            invert: true,
+            binary_as_text: false,
+        };
+        let output = searcher.search(&LiteralMatcher::new("match"), CONTENTS);
+
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8, 9], numbers(&output));
+        assert!(matches!(
+            output[1],
+            SearchLine::Context {
+                line: "two match",
+                ..
+            }
+        ));
+        assert!(matches!(
+            output[7],
+            SearchLine::Context {
+                line: "eight match",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn count_respects_invert() {
+        let matcher = LiteralMatcher::new("match");
+        let searcher = Searcher::default();
+        assert_eq!(3, searcher.count(&matcher, CONTENTS.as_bytes()).unwrap());
+
+        let searcher = Searcher {
+            invert: true,
+            ..Searcher::default()
+        };
+        assert_eq!(6, searcher.count(&matcher, CONTENTS.as_bytes()).unwrap());
+    }
+
+    // A reader that fails as soon as we ask it for anything. Chained after some lines, it makes reading past them an error.
+    struct Broken;
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("read too far"))
+        }
+    }
+
+    #[test]
+    fn has_match_stops_at_the_first_match() {
+        let contents = || b"two match\n".chain(Broken);
+        let searcher = Searcher::default();
+
+        assert!(searcher
+            .has_match(&LiteralMatcher::new("match"), contents())
+            .unwrap());
+        assert!(searcher
+            .has_match(&LiteralMatcher::new("missing"), contents())
+            .is_err());
+    }
+
+    #[test]
+    fn emit_can_stop_the_search() {
+        let contents = || b"one fish\ntwo fish\n".chain(Broken);
+        for searcher in [
+            Searcher::default(),
+            Searcher {
+                after_context: 1,
+                ..Searcher::default()
+            },
+        ] {
+            let mut lines = 0;
+            searcher
+                .search_reader(
+                    &RegexMatcher::new("fish", false).unwrap(),
+                    contents(),
+                    |_| {
+                        lines += 1;
+                        false
+                    },
+                )
+                .unwrap();
+            assert_eq!(1, lines);
+        }
+
+        let mut lines = 0;
+        Searcher::default()
+            .search_raw(&LiteralMatcher::new("fish"), contents(), |_| {
+                lines += 1;
+                false
+            })
+            .unwrap();
+        assert_eq!(1, lines);
+    }
+
+    #[test]
+    fn searches_a_file_much_bigger_than_the_buffer() {
+        // Around 3.5MB of lines, with a match every 1000 lines, which is over fifty times the size of the buffer we read it through.
+        let path = std::env::temp_dir().join(format!("cli-big-{}.txt", std::process::id()));
+        let mut contents = String::new();
+        for number in 1..=100_000 {
+            if number % 1000 == 0 {
+                contents.push_str(&format!("line {number} has a needle in it\n"));
+            } else {
+                contents.push_str(&format!("line {number} is just filler text here\n"));
+            }
+        }
+        std::fs::write(&path, &contents).unwrap();
+        assert!(contents.len() > 50 * crate::lines::DEFAULT_CAPACITY);
+
+        let matcher = LiteralMatcher::new("needle");
+        let mut found = Vec::new();
+        Searcher::default()
+            .search_reader(&matcher, std::fs::File::open(&path).unwrap(), |line| {
+                if let SearchLine::Match(found_line) = line {
+                    found.push((found_line.line_number, found_line.byte_offset));
+                }
+                true
+            })
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(100, found.len());
+        let (line_number, byte_offset) = found[99];
+        assert_eq!(100_000, line_number);
+        assert!(contents[byte_offset..].starts_with("line 100000 has a needle"));
+    }
+
+    #[test]
+    fn literal_fast_path_agrees_with_line_by_line() {
+        let contents = "fish\r\nno\n\none fish two fish\nFISH\n  fish\nnö fish";
+        // A regex matcher has no Finder, so it always goes line by line.
+        let slow = RegexMatcher::new("fish", false).unwrap();
+        let fast = LiteralMatcher::new("fish");
+        assert!(fast.finder().is_some() && slow.finder().is_none());
+
+        let searcher = Searcher::default();
+        assert_eq!(
+            searcher.search(&slow, contents),
+            searcher.search(&fast, contents)
+        );
+        assert_eq!(
+            searcher.count(&slow, contents.as_bytes()).unwrap(),
+            searcher.count(&fast, contents.as_bytes()).unwrap()
+        );
+
+        // Bad UTF-8 in lines we skip over still makes the rest of the input binary.
+        let contents: &[u8] = b"fish\n\xff\nfish\n";
+        let mut lines = Vec::new();
+        searcher
+            .search_reader(&fast, contents, |line| {
+                lines.push(format!("{line:?}"));
+                true
+            })
+            .unwrap();
+        assert_eq!(2, lines.len());
+        assert_eq!("BinaryMatch", lines[1]);
+    }
+
+    #[test]
+    fn binary_input_reports_one_binary_match() {
+        let contents: &[u8] = b"plain text\nfish \x00\x01\x02\nfish again\n";
+        let searcher = Searcher {
+            before_context: 1,
+            ..Searcher::default()
+        };
+        let matcher = CaseInsensitiveMatcher::new("fish");
+
+        let mut lines = Vec::new();
+        searcher
+            .search_reader(&matcher, contents, |line| {
+                lines.push(format!("{line:?}"));
+                true
+            })
+            .unwrap();
+        assert_eq!(vec!["BinaryMatch"], lines);
+        // Counting still works, since it never prints any lines.
+        assert_eq!(2, searcher.count(&matcher, contents).unwrap());
+
+        let searcher = Searcher {
+            binary_as_text: true,
+            ..Searcher::default()
+        };
+        let mut found = Vec::new();
+        searcher
+            .search_reader(&matcher, b"\xfffish\n".as_slice(), |line| {
+                if let SearchLine::Match(found_line) = line {
+                    found.push((found_line.line.to_string(), found_line.ranges));
+                }
+                true
+            })
+            .unwrap();
+        assert_eq!(
+            vec![("\u{fffd}fish".to_string(), vec![Range { start: 3, end: 7 }])],
+            found
+        );
+    }
+
+    #[test]
+    fn only_real_binary_looks_binary() {
+        assert!(looks_binary(b"text\x00"));
+        assert!(looks_binary(b"caf\xe9 latin-1"));
+        assert!(!looks_binary("café".as_bytes()));
+        // The first byte of a two-byte "é" with the second cut off.
+        assert!(!looks_binary(b"caf\xc3"));
+    }
+
+    #[test]
+    fn search_raw_maps_ranges_back_to_bytes() {
+        // \xff is one bad byte, and \xe2\x82 is the start of a three byte character that never finishes. Each turns into a three byte U+FFFD for the matcher, which would throw every later range off if we didn't map them back.
+        let contents: &[u8] = b"ok fish\n\xff fish \xe2\x82 fish\r\nnothing\n";
+        let matcher = LiteralMatcher::new("fish");
+        let mut found = Vec::new();
+
+        let read = Searcher::default()
+            .search_raw(&matcher, contents, |line| {
+                found.push((
+                    line.line_number,
+                    line.byte_offset,
+                    line.ranges,
+                    line.bytes.to_vec(),
+                ));
+                true
+            })
+            .unwrap();
+
+        assert_eq!(contents.len(), read);
+        assert_eq!(2, found.len());
+        assert_eq!(
+            (1, 0, vec![Range { start: 3, end: 7 }]),
+            (found[0].0, found[0].1, found[0].2.clone())
+        );
+        let (line_number, byte_offset, ranges, bytes) = &found[1];
+        assert_eq!((2, 8), (*line_number, *byte_offset));
+        assert_eq!(vec![2..6, 10..14], *ranges);
+        assert_eq!(b"\xff fish \xe2\x82 fish", bytes.as_slice());
+    }
+}
@@ -0,0 +1,396 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    io::{self, Read},
+    rc::Rc,
+};
+
+use encoding_rs::{DecoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+use crate::lines::DEFAULT_CAPACITY;
+
+// Everything past the reader only knows how to search UTF-8, so a file in any other encoding gets turned into UTF-8 on its way in, a buffer at a time, by a Transcoder. The decoding itself is encoding_rs, which knows every encoding a web browser does (UTF-16, Windows-1252, Shift_JIS and so on).
+
+// Which encoding a file needs decoding from, and how long its byte order mark is. A BOM is the most reliable thing we've got, so it wins over --encoding. Without one we go with --encoding, and with neither we assume UTF-8, which needs no decoding at all (that's the None).
+pub fn sniff(
+    start: &[u8],
+    encoding: Option<&'static Encoding>,
+) -> Option<(&'static Encoding, usize)> {
+    match Encoding::for_bom(start) {
+        Some(found) => Some(found),
+        None => encoding
+            .filter(|&encoding| encoding != UTF_8)
+            .map(|encoding| (encoding, 0)),
+    }
+}
+
+// Whether the start of a file we're about to decode from `encoding` looks like it came from a binary file. UTF-16 is full of NUL bytes, so its raw bytes can't tell us anything. Every other encoding leaves NUL as a single 0 byte, which text files essentially never have, so that still works for them. Bytes that aren't UTF-8 don't count here, since that's what text in most other encodings looks like.
+pub fn looks_binary_before_decoding(start: &[u8], encoding: &'static Encoding) -> bool {
+    !is_utf16(encoding) && memchr::memchr(0, start).is_some()
+}
+
+fn is_utf16(encoding: &'static Encoding) -> bool {
+    encoding == UTF_16LE || encoding == UTF_16BE
+}
+
+// How many bytes some text took up in the original file. We use it to work out where a match starts inside a line, since the line's own offset comes from Offsets.
+pub fn encoded_len(encoding: &'static Encoding, text: &str) -> usize {
+    if is_utf16(encoding) {
+        // encode() would give us UTF-8 for these (browsers never send UTF-16), so we count the code units ourselves.
+        text.encode_utf16().count() * 2
+    } else if encoding == UTF_8 {
+        text.len()
+    } else if encoding.encode("\u{fffd}").2 {
+        // With --lossy, a U+FFFD stands for bytes that weren't valid in the encoding. encode() has no way to get those back and writes "&#65533;" instead, which would throw every offset after it off by 7. In a single-byte encoding it was exactly one byte, and that's the best guess we've got for the others too.
+        let replaced = text.matches('\u{fffd}').count();
+        text.split('\u{fffd}')
+            .map(|piece| encoding.encode(piece).0.len())
+            .sum::<usize>()
+            + replaced
+    } else {
+        // GB18030 can hold a real U+FFFD, so there we can't tell it apart from a replacement and just count what it encodes to.
+        encoding.encode(text).0.len()
+    }
+}
+
+// Maps the start of each line in the UTF-8 we hand out back to where it started in the original file, so -b and --json can report offsets you could seek to.
+//
+// There's an entry for every line, but only a handful are ever needed at once, so we throw the rest away as we go. A lookup drops everything before it, since lookups only ever move forwards, the same way the searcher goes through a file. That's not enough on its own though: a file with no matches has nothing looked up at all. So the Transcoder also drops lines the searcher has finished with every time it's asked for more (see forget_before()).
+#[derive(Debug, Default)]
+pub struct Offsets {
+    // Pairs of (UTF-8 offset, original offset), one for the start of each line.
+    starts: VecDeque<(usize, usize)>,
+    // How many lines before the one being read we hang on to.
+    keep: usize,
+}
+
+impl Offsets {
+    // Forgets the lines before the one `delivered` is in, apart from the last `keep` of them.
+    //
+    // This relies on how LineReader reads: it only asks for more once every complete line it already has has been searched, so at that point the only lines still to come are the unfinished one at the end and everything after it. The searcher can still show a few lines before it as before-context though, which is what `keep` is for.
+    fn forget_before(&mut self, delivered: usize) {
+        let started = self
+            .starts
+            .partition_point(|&(decoded, _)| decoded <= delivered);
+        self.starts.drain(..started.saturating_sub(self.keep + 1));
+    }
+
+    pub fn original(&mut self, decoded: usize) -> usize {
+        while self.starts.len() > 1 && self.starts[1].0 <= decoded {
+            self.starts.pop_front();
+        }
+        match self.starts.front() {
+            Some(&(line_start, original)) => original + decoded.saturating_sub(line_start),
+            None => decoded,
+        }
+    }
+}
+
+// A reader that decodes whatever `reader` gives it from `encoding` into UTF-8.
+//
+// Anything that isn't valid in the encoding is an InvalidData error unless `lossy` is set, in which case it becomes U+FFFD. Quietly searching a mangled file is worse than being told it's in a different encoding than you thought.
+pub struct Transcoder<R> {
+    reader: R,
+    encoding: &'static Encoding,
+    decoder: encoding_rs::Decoder,
+    lossy: bool,
+    // Bytes from `reader` we haven't decoded yet live in input[start..end].
+    input: Vec<u8>,
+    start: usize,
+    end: usize,
+    eof: bool,
+    // Decoded bytes waiting to be read live in output[output_start..].
+    output: Vec<u8>,
+    output_start: usize,
+    finished: bool,
+    // How many bytes of the original file we've decoded, and how many bytes of UTF-8 that turned into.
+    consumed: usize,
+    produced: usize,
+    // Only kept when someone asked for them, since they cost a little for every line.
+    offsets: Option<Rc<RefCell<Offsets>>>,
+}
+
+impl<R: Read> Transcoder<R> {
+    // `reader` should start just after the BOM, if there was one. `bom_len` says how long it was, so offsets still count from the real start of the file.
+    pub fn new(
+        reader: R,
+        encoding: &'static Encoding,
+        bom_len: usize,
+        lossy: bool,
+    ) -> Transcoder<R> {
+        Transcoder {
+            reader,
+            encoding,
+            decoder: encoding.new_decoder_without_bom_handling(),
+            lossy,
+            input: vec![0; DEFAULT_CAPACITY],
+            start: 0,
+            end: 0,
+            eof: false,
+            output: Vec::new(),
+            output_start: 0,
+            finished: false,
+            consumed: bom_len,
+            produced: 0,
+            offsets: None,
+        }
+    }
+
+    // Starts keeping track of where lines began in the original file, and hands back the Offsets they're kept in. `keep` is how many lines before the one being read might still be looked up, which is the searcher's before-context.
+    pub fn track_offsets(&mut self, keep: usize) -> Rc<RefCell<Offsets>> {
+        let offsets = Rc::new(RefCell::new(Offsets {
+            keep,
+            ..Offsets::default()
+        }));
+        offsets
+            .borrow_mut()
+            .starts
+            .push_back((self.produced, self.consumed));
+        self.offsets = Some(Rc::clone(&offsets));
+        offsets
+    }
+
+    // Decodes the next piece of input into `output`. When we're keeping track of offsets, a piece never goes past the end of a line, so we know exactly where in the original file each line ended.
+    fn decode_more(&mut self) -> io::Result<()> {
+        let (mut piece_end, mut ends_line) = self.next_piece();
+        if piece_end == self.start && !self.eof {
+            self.fill()?;
+            (piece_end, ends_line) = self.next_piece();
+        }
+        let last = self.eof && piece_end == self.end;
+        let piece = &self.input[self.start..piece_end];
+
+        let (read, written) = if self.lossy {
+            let capacity = max_output(self.decoder.max_utf8_buffer_length(piece.len()))?;
+            self.output.resize(capacity, 0);
+            let (_, read, written, _) = self.decoder.decode_to_utf8(piece, &mut self.output, last);
+            (read, written)
+        } else {
+            let capacity = max_output(
+                self.decoder
+                    .max_utf8_buffer_length_without_replacement(piece.len()),
+            )?;
+            self.output.resize(capacity, 0);
+            let (result, read, written) =
+                self.decoder
+                    .decode_to_utf8_without_replacement(piece, &mut self.output, last);
+            // The bad bytes are the last ones read, apart from `after` bytes encoding_rs had to look at to be sure.
+            if let DecoderResult::Malformed(bad, after) = result {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "not valid {} at byte {} (--lossy searches it anyway)",
+                        self.encoding.name(),
+                        self.consumed + read - bad as usize - after as usize
+                    ),
+                ));
+            }
+            (read, written)
+        };
+
+        self.output.truncate(written);
+        self.output_start = 0;
+        self.start += read;
+        self.consumed += read;
+        self.produced += written;
+        if let Some(offsets) = &self.offsets {
+            if ends_line || last {
+                offsets
+                    .borrow_mut()
+                    .starts
+                    .push_back((self.produced, self.consumed));
+            }
+        }
+        self.finished = last;
+        Ok(())
+    }
+
+    // Where the next piece of input to decode ends, and whether that's the end of a line.
+    fn next_piece(&self) -> (usize, bool) {
+        let unread = &self.input[self.start..self.end];
+        if self.offsets.is_none() {
+            return (self.end, false);
+        }
+
+        if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            // A '\n' is a whole two-byte code unit here, and a 0x0A byte on its own could just be half of some other character. Pieces always end on a code unit, so the units line up with the start of `unread`.
+            let newline: [u8; 2] = if self.encoding == UTF_16LE {
+                [b'\n', 0]
+            } else {
+                [0, b'\n']
+            };
+            if let Some(unit) = unread.chunks_exact(2).position(|unit| unit == newline) {
+                return (self.start + unit * 2 + 2, true);
+            }
+            // Hold back an odd byte at the end until the rest of its code unit arrives, unless there's nothing more coming.
+            if self.eof {
+                return (self.end, false);
+            }
+            return (self.start + unread.len() / 2 * 2, false);
+        }
+
+        // Every other encoding encoding_rs knows keeps '\n' as a plain 0x0A byte that can't be part of anything else.
+        match memchr::memchr(b'\n', unread) {
+            Some(newline) => (self.start + newline + 1, true),
+            None => (self.end, false),
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        // Slide anything we held back down to the front to make room behind it.
+        self.input.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.start = 0;
+
+        loop {
+            match self.reader.read(&mut self.input[self.end..]) {
+                Ok(0) => self.eof = true,
+                Ok(read) => self.end += read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+            return Ok(());
+        }
+    }
+}
+
+// encoding_rs says None when the buffer it would need is bigger than a usize, which can't happen for pieces of our buffer, but it's cheap to check.
+fn max_output(length: Option<usize>) -> io::Result<usize> {
+    length.ok_or_else(|| io::Error::other("too much input to decode at once"))
+}
+
+impl<R: Read> Read for Transcoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(offsets) = &self.offsets {
+            let delivered = self.produced - (self.output.len() - self.output_start);
+            offsets.borrow_mut().forget_before(delivered);
+        }
+        while self.output_start == self.output.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.decode_more()?;
+        }
+        let available = &self.output[self.output_start..];
+        let length = available.len().min(buf.len());
+        buf[..length].copy_from_slice(&available[..length]);
+        self.output_start += length;
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LineReader;
+    use encoding_rs::WINDOWS_1252;
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend(text.encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+        bytes
+    }
+
+    fn decode_all(mut reader: impl Read) -> io::Result<String> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    #[test]
+    fn bom_wins_over_the_chosen_encoding() {
+        assert_eq!(
+            Some((UTF_16LE, 2)),
+            sniff(&utf16le("hi"), Some(WINDOWS_1252))
+        );
+        assert_eq!(
+            Some((WINDOWS_1252, 0)),
+            sniff(b"caf\xe9", Some(WINDOWS_1252))
+        );
+        assert_eq!(None, sniff(b"plain", None));
+        assert_eq!(None, sniff(b"plain", Some(UTF_8)));
+    }
+
+    #[test]
+    fn nul_bytes_look_binary_unless_its_utf16() {
+        assert!(looks_binary_before_decoding(b"\0\0\0\0", WINDOWS_1252));
+        assert!(!looks_binary_before_decoding(b"caf\xe9", WINDOWS_1252));
+        assert!(!looks_binary_before_decoding(&utf16le("hi"), UTF_16LE));
+    }
+
+    #[test]
+    fn maps_line_starts_back_to_the_original_file() {
+        let bytes = utf16le("one\r\ncafé two\nthree");
+        // A tiny buffer, so lines and code units get split between reads.
+        let mut transcoder = Transcoder::new(&bytes[2..], UTF_16LE, 2, false);
+        transcoder.input = vec![0; 3];
+        let offsets = transcoder.track_offsets(0);
+
+        // Looking each line up as it's read, the way the searcher does.
+        let mut lines = LineReader::with_capacity(transcoder, 4);
+        let mut starts = Vec::new();
+        let mut decoded = 0;
+        while let Some(line) = lines.next_line().unwrap() {
+            starts.push(offsets.borrow_mut().original(decoded));
+            decoded += line.len();
+        }
+        // "one\r\n" is 5 bytes of UTF-8 and 10 of UTF-16, "café two\n" is 10 and 18.
+        assert_eq!(vec![2, 12, 30], starts);
+        // The end of the file, for bytes_searched.
+        assert_eq!(40, offsets.borrow_mut().original(decoded));
+    }
+
+    #[test]
+    fn forgets_lines_the_searcher_is_done_with() {
+        let line = "a line\n";
+        let bytes = utf16le(&line.repeat(100_000));
+        let mut transcoder = Transcoder::new(&bytes[2..], UTF_16LE, 2, false);
+        let offsets = transcoder.track_offsets(2);
+
+        let mut lines = LineReader::new(transcoder);
+        let mut starts = VecDeque::new();
+        let mut decoded = 0;
+        let mut most = 0;
+        while let Some(read) = lines.next_line().unwrap() {
+            most = most.max(offsets.borrow().starts.len());
+            starts.push_back(decoded);
+            if starts.len() > 3 {
+                starts.pop_front();
+            }
+            // Every thousandth line "matches", and gets looked up along with two lines of before-context. It's all ASCII, so each byte of UTF-8 was two bytes of UTF-16.
+            if starts.len() == 3 && starts[2] / line.len() % 1000 == 999 {
+                for &start in &starts {
+                    assert_eq!(2 + start * 2, offsets.borrow_mut().original(start));
+                }
+            }
+            decoded += read.len();
+        }
+
+        // About a buffer's worth of lines at most, rather than all 100,000.
+        assert!(most <= DEFAULT_CAPACITY / line.len() + 4, "{most}");
+    }
+
+    #[test]
+    fn invalid_input_is_an_error_unless_lossy() {
+        // A lone low surrogate can't be decoded.
+        let bytes: &[u8] = b"o\x00k\x00\x00\xdc";
+        let err = decode_all(Transcoder::new(bytes, UTF_16LE, 0, false)).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(err.to_string().contains("at byte 4"));
+
+        assert_eq!(
+            "ok\u{fffd}",
+            decode_all(Transcoder::new(bytes, UTF_16LE, 0, true)).unwrap()
+        );
+    }
+
+    #[test]
+    fn measures_text_in_the_original_encoding() {
+        // Four characters of two bytes each, then one that needs a surrogate pair.
+        assert_eq!(12, encoded_len(UTF_16LE, "café\u{1f41f}"));
+        assert_eq!(4, encoded_len(WINDOWS_1252, "café"));
+        // What --lossy leaves in place of a byte that isn't valid windows-1252.
+        assert_eq!(6, encoded_len(WINDOWS_1252, "caf\u{fffd}\u{fffd}é"));
+        assert_eq!(5, encoded_len(UTF_8, "café"));
+    }
+}
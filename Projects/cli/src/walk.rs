@@ -0,0 +1,351 @@
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::filter::{Overrides, TypeFilter};
+use crate::gitignore::IgnoreFile;
+
+// What Walk leaves out. By default that's hidden files and anything an ignore file (.gitignore, .ignore or .git/info/exclude) says to ignore, since in a real project those are mostly build output and dependencies nobody meant to search.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalkOptions {
+    // Include files and directories whose names start with a dot (--hidden).
+    pub hidden: bool,
+    // Don't read any ignore files (--no-ignore).
+    pub no_ignore: bool,
+    // The -g globs, in the order they were given.
+    pub globs: Vec<String>,
+    // File name globs from --type, of which a file has to match one, and from --type-not, of which it can't match any.
+    pub types: Vec<String>,
+    pub types_not: Vec<String>,
+}
+
+// Walk hands back every regular file underneath a directory, one at a time. It's an iterator (just like the ones in our iterator notes) so run() can start searching the first file before we've finished listing the whole tree.
+pub struct Walk {
+    hidden: bool,
+    no_ignore: bool,
+    overrides: Overrides,
+    types: TypeFilter,
+    // Paths still waiting to be looked at, along with how deep in the tree they are. We pop from the end, so this behaves like a depth-first search.
+    stack: Vec<(PathBuf, usize)>,
+    // The canonical paths of the directories we're currently inside. If a symlink points back at one of these, following it would make us go round in circles forever.
+    ancestors: Vec<PathBuf>,
+    // The ignore files in each of the directories we're currently inside, lined up with `ancestors`.
+    ignores: Vec<Vec<IgnoreFile>>,
+    // The ignore files in directories above the root, which still apply if the root is somewhere inside a git repository.
+    ignores_above: Vec<IgnoreFile>,
+}
+
+impl Walk {
+    pub fn new(root: &Path) -> Walk {
+        Walk::with_options(root, WalkOptions::default())
+    }
+
+    pub fn with_options(root: &Path, options: WalkOptions) -> Walk {
+        Walk {
+            hidden: options.hidden,
+            no_ignore: options.no_ignore,
+            overrides: Overrides::new(root, &options.globs),
+            types: TypeFilter::new(&options.types, &options.types_not),
+            stack: vec![(root.to_path_buf(), 0)],
+            ancestors: Vec::new(),
+            ignores: Vec::new(),
+            ignores_above: if options.no_ignore {
+                Vec::new()
+            } else {
+                ignore_files_above(root)
+            },
+        }
+    }
+
+    // Whether to leave out something we found. A -g glob has the final say, then ignore files, and file types go last since they only care about file names.
+    fn leaves_out(&self, path: &Path, is_dir: bool) -> bool {
+        if let Some(included) = self.overrides.check(path, is_dir) {
+            return !included;
+        }
+        if !self.no_ignore && self.is_ignored(path, is_dir) {
+            return true;
+        }
+        !is_dir && !self.types.allows(path)
+    }
+
+    // The deepest ignore file with something to say about a path decides, the same way a .gitignore in a subdirectory can override the one at the top of the repo.
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.ignores
+            .iter()
+            .rev()
+            .flatten()
+            .chain(&self.ignores_above)
+            .find_map(|file| file.check(path, is_dir))
+            .unwrap_or(false)
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Result<PathBuf, WalkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, depth)) = self.stack.pop() {
+            // Anything deeper than the entry we just popped belongs to a directory we've already finished with.
+            self.ancestors.truncate(depth);
+            self.ignores.truncate(depth);
+
+            // The root is always searched, even if it's hidden itself (like `.`), since you asked for it by name.
+            if depth > 0 && !self.hidden && is_hidden(&path) {
+                continue;
+            }
+
+            // fs::metadata follows symlinks, so a link to a file looks like a file and a link to a directory looks like a directory.
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(err) => return Some(Err(WalkError::Io(path, err))),
+            };
+
+            if depth > 0 && self.leaves_out(&path, metadata.is_dir()) {
+                continue;
+            }
+
+            if metadata.is_file() {
+                return Some(Ok(path));
+            }
+            if !metadata.is_dir() {
+                // Sockets, fifos and device files aren't something we want to read lines out of.
+                continue;
+            }
+
+            let canonical = match fs::canonicalize(&path) {
+                Ok(canonical) => canonical,
+                Err(err) => return Some(Err(WalkError::Io(path, err))),
+            };
+            if self.ancestors.contains(&canonical) {
+                return Some(Err(WalkError::Loop(path)));
+            }
+
+            let children = match read_dir_sorted(&path) {
+                Ok(children) => children,
+                Err(err) => return Some(Err(WalkError::Io(path, err))),
+            };
+
+            self.ancestors.push(canonical);
+            // Pushing in reverse means the alphabetically first entry gets popped first, which keeps our output in a predictable order.
+            for child in children.into_iter().rev() {
+                self.stack.push((child, depth + 1));
+            }
+
+            if self.no_ignore {
+                self.ignores.push(Vec::new());
+                continue;
+            }
+            match read_ignore_files(&path) {
+                Ok(files) => self.ignores.push(files),
+                // An ignore file we can't read gets reported, but the directory's still searched without it.
+                Err(err) => {
+                    self.ignores.push(Vec::new());
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+// The ignore files in one directory, most important first: .ignore is for rules only this tool should follow, so it wins over .gitignore, and .git/info/exclude (only at the top of a repo) comes last.
+fn read_ignore_files(dir: &Path) -> Result<Vec<IgnoreFile>, WalkError> {
+    let mut files = Vec::new();
+    for name in [".ignore", ".gitignore", ".git/info/exclude"] {
+        let file = dir.join(name);
+        match IgnoreFile::read(dir, &file) {
+            Ok(Some(rules)) => files.push(rules),
+            Ok(None) => {}
+            Err(err) => return Err(WalkError::Io(file, err)),
+        }
+    }
+    Ok(files)
+}
+
+// The ignore files in the directories above `root`, nearest first, going up as far as the top of the git repository `root` is in. Outside a repository there's no telling which of them were meant for us, so we don't use any. Unreadable ones are skipped without a fuss, since they're outside what we were asked to search.
+fn ignore_files_above(root: &Path) -> Vec<IgnoreFile> {
+    let Ok(canonical) = fs::canonicalize(root) else {
+        return Vec::new();
+    };
+    if canonical.join(".git").exists() {
+        return Vec::new();
+    }
+
+    let mut found = Vec::new();
+    for dir in canonical.ancestors().skip(1) {
+        let prefix = canonical.strip_prefix(dir).unwrap_or(&canonical);
+        let files = read_ignore_files(dir).unwrap_or_default();
+        found.extend(
+            files
+                .into_iter()
+                .map(|file| file.above(root, prefix.to_path_buf())),
+        );
+        if dir.join(".git").exists() {
+            return found;
+        }
+    }
+    Vec::new()
+}
+
+fn read_dir_sorted(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut children = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    children.sort();
+    Ok(children)
+}
+
+// A problem with one part of the tree. These are meant to be reported and then skipped, rather than ending the whole search.
+#[derive(Debug)]
+pub enum WalkError {
+    Io(PathBuf, io::Error),
+    Loop(PathBuf),
+}
+
+impl fmt::Display for WalkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WalkError::Io(path, err) => write!(f, "{}: {err}", path.display()),
+            WalkError::Loop(path) => {
+                write!(f, "{}: filesystem loop detected, skipping", path.display())
+            }
+        }
+    }
+}
+
+impl Error for WalkError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh, empty directory under the system temp dir for each test, so tests running in parallel don't trip over each other.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cli-walk-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn walks_nested_directories_in_order() {
+        let root = scratch_dir("nested");
+        fs::create_dir_all(root.join("b/inner")).unwrap();
+        fs::write(root.join("c.txt"), "c").unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("b/inner/d.txt"), "d").unwrap();
+
+        let files: Vec<PathBuf> = Walk::new(&root).map(Result::unwrap).collect();
+
+        assert_eq!(
+            vec![
+                root.join("a.txt"),
+                root.join("b/inner/d.txt"),
+                root.join("c.txt")
+            ],
+            files
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn skips_ignored_and_hidden_files() {
+        let root = scratch_dir("ignore");
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::create_dir_all(root.join("src/gen")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.log\n!keep.log\n").unwrap();
+        fs::write(root.join(".git/info/exclude"), "notes.txt\n").unwrap();
+        fs::write(root.join("src/.gitignore"), "gen/\n").unwrap();
+        fs::write(root.join("src/.ignore"), "!debug.log\n").unwrap();
+        for file in [
+            ".env",
+            "keep.log",
+            "main.log",
+            "notes.txt",
+            "src/debug.log",
+            "src/gen/out.rs",
+            "src/main.rs",
+            "src/trace.log",
+            "target/debug/app",
+        ] {
+            fs::write(root.join(file), "text").unwrap();
+        }
+
+        let files: Vec<PathBuf> = Walk::new(&root).map(Result::unwrap).collect();
+        assert_eq!(
+            vec![
+                root.join("keep.log"),
+                root.join("src/debug.log"),
+                root.join("src/main.rs")
+            ],
+            files
+        );
+
+        // Starting further down still uses the .gitignore at the top of the repo.
+        let files: Vec<PathBuf> = Walk::new(&root.join("src")).map(Result::unwrap).collect();
+        assert_eq!(
+            vec![root.join("src/debug.log"), root.join("src/main.rs")],
+            files
+        );
+
+        let options = WalkOptions {
+            hidden: true,
+            no_ignore: true,
+            ..WalkOptions::default()
+        };
+        let files = Walk::with_options(&root, options).count();
+        // All nine files, plus the three ignore files and .git/info/exclude.
+        assert_eq!(13, files);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn filters_by_glob_and_type() {
+        let root = scratch_dir("filter");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("vendor")).unwrap();
+        for file in ["Cargo.toml", "README.md", "src/main.rs", "vendor/lib.rs"] {
+            fs::write(root.join(file), "text").unwrap();
+        }
+
+        let options = WalkOptions {
+            globs: vec![String::from("!vendor/")],
+            types: vec![String::from("*.rs"), String::from("Cargo.toml")],
+            ..WalkOptions::default()
+        };
+        let files: Vec<PathBuf> = Walk::with_options(&root, options)
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(
+            vec![root.join("Cargo.toml"), root.join("src/main.rs")],
+            files
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_symlink_loops_and_keeps_going() {
+        let root = scratch_dir("loop");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("sub/file.txt"), "text").unwrap();
+        std::os::unix::fs::symlink(&root, root.join("sub/back")).unwrap();
+
+        let results: Vec<_> = Walk::new(&root).collect();
+
+        assert_eq!(2, results.len());
+        assert!(matches!(&results[0], Err(WalkError::Loop(path)) if path.ends_with("sub/back")));
+        assert_eq!(root.join("sub/file.txt"), *results[1].as_ref().unwrap());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}